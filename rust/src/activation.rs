@@ -5,11 +5,14 @@ struct ScalarActivationFN {
     backward: fn(f64) -> f64,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ActivationFunctionKey {
     Sigmoid,
     TanH,
     ReLu,
     Softmax,
+    /// Passes the sum through unchanged.
+    Linear,
 }
 
 const SCALAR_RELU: ScalarActivationFN = ScalarActivationFN {
@@ -48,59 +51,63 @@ const SCALAR_SIGMOID: ScalarActivationFN = ScalarActivationFN {
 };
 
 pub struct ActivationFN {
-    pub forward: fn(sum: &Matrix, isOutput: bool) -> Matrix,
-    pub backward: fn(sum: &Matrix) -> Matrix,
-    pub output: fn(output: &Matrix, expected: &Matrix) -> Matrix,
+    pub forward: fn(sum: &Matrix) -> Matrix,
+    /// Gradient with respect to `sum` given `gradient`, the gradient with
+    /// respect to the `activated` output.
+    pub backward: fn(sum: &Matrix, activated: &Matrix, gradient: &Matrix) -> Matrix,
 }
 
 pub const RELU_ACTIVATION: ActivationFN = ActivationFN {
-    forward: |sum, is_output| -> Matrix { activate(SCALAR_RELU.forward, sum, !is_output) },
-    backward: |sum| -> Matrix { backpropagate(SCALAR_RELU.backward, sum) },
-    output: |sum, _| -> Matrix { backpropagate(SCALAR_RELU.forward, sum) },
+    forward: |sum| -> Matrix { activate(SCALAR_RELU.forward, sum) },
+    backward: |sum, _, gradient| -> Matrix { backpropagate(SCALAR_RELU.backward, sum, gradient) },
 };
 
 pub const TANH_ACTIVATION: ActivationFN = ActivationFN {
-    forward: |sum, is_output| -> Matrix { activate(SCALAR_TANH.forward, sum, !is_output) },
-    backward: |sum| -> Matrix { backpropagate(SCALAR_TANH.backward, sum) },
-    output: |sum, _| -> Matrix { backpropagate(SCALAR_TANH.forward, sum) },
+    forward: |sum| -> Matrix { activate(SCALAR_TANH.forward, sum) },
+    backward: |sum, _, gradient| -> Matrix { backpropagate(SCALAR_TANH.backward, sum, gradient) },
 };
 
 pub const SIGMOID_ACTIVATION: ActivationFN = ActivationFN {
-    forward: |sum, is_output| -> Matrix { activate(SCALAR_SIGMOID.forward, sum, !is_output) },
-    backward: |sum| -> Matrix { backpropagate(SCALAR_SIGMOID.backward, sum) },
-    output: |sum, _| -> Matrix { backpropagate(SCALAR_SIGMOID.forward, sum) },
+    forward: |sum| -> Matrix { activate(SCALAR_SIGMOID.forward, sum) },
+    backward: |sum, _, gradient| -> Matrix {
+        backpropagate(SCALAR_SIGMOID.backward, sum, gradient)
+    },
 };
 
-pub const IDENTITY_ACTIVATION: ActivationFN = ActivationFN {
-    forward: |sum, is_output| -> Matrix { activate(|x| -> f64 { x }, sum, !is_output) },
-    backward: |_| -> Matrix { panic!("use identity only on first layer") },
-    output: |_, _| -> Matrix { panic!("use identity only on first layer") },
+pub const LINEAR_ACTIVATION: ActivationFN = ActivationFN {
+    forward: |sum| -> Matrix { sum.clone() },
+    backward: |_, _, gradient| -> Matrix { gradient.clone() },
 };
 
 pub const SOFTMAX_ACTIVATION: ActivationFN = ActivationFN {
-    forward: |sum, is_output| -> Matrix {
-        let result = softmax_fn(sum);
-        if !is_output {
-            return result.unshift(1.0);
-        }
-        result
+    forward: softmax_fn,
+    // Jacobian-vector product: a * (g - a . g).
+    backward: |_, activated, gradient| -> Matrix {
+        let dot: f64 = (0..activated.rows)
+            .map(|i| activated.get(i, 0) * gradient.get(i, 0))
+            .sum();
+        activated.map(&|a, i, j| -> f64 { a * (gradient.get(i, j) - dot) })
     },
-    output: |sum, expected| -> Matrix { sum.subtract(expected) },
-    backward: |_| -> Matrix { panic!("use softmax only on output") },
 };
 
-fn activate(mapper: fn(f64) -> f64, sum: &Matrix, append_bias: bool) -> Matrix {
-    let vect = sum.map(&|x, _, _| -> f64 { mapper(x) });
-    if append_bias {
-        return vect.unshift(1.0);
+pub fn activation_function(key: ActivationFunctionKey) -> ActivationFN {
+    match key {
+        ActivationFunctionKey::ReLu => RELU_ACTIVATION,
+        ActivationFunctionKey::Sigmoid => SIGMOID_ACTIVATION,
+        ActivationFunctionKey::TanH => TANH_ACTIVATION,
+        ActivationFunctionKey::Softmax => SOFTMAX_ACTIVATION,
+        ActivationFunctionKey::Linear => LINEAR_ACTIVATION,
     }
-    vect
 }
 
-fn backpropagate(mapper: fn(f64) -> f64, sum: &Matrix) -> Matrix {
+fn activate(mapper: fn(f64) -> f64, sum: &Matrix) -> Matrix {
     sum.map(&|x, _, _| -> f64 { mapper(x) })
 }
 
+fn backpropagate(mapper: fn(f64) -> f64, sum: &Matrix, gradient: &Matrix) -> Matrix {
+    sum.map(&|x, i, j| -> f64 { mapper(x) * gradient.get(i, j) })
+}
+
 fn softmax_fn(vector: &Matrix) -> Matrix {
     let mut max = vector.get(0, 0);
     vector.iterate(&mut |value, _| {
@@ -116,3 +123,79 @@ fn softmax_fn(vector: &Matrix) -> Matrix {
 
     vector.map(&|x, _, _| -> f64 { (x - max).exp() / sum })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const H: f64 = 1e-6;
+
+    fn numeric_derivative(f: fn(f64) -> f64, x: f64) -> f64 {
+        (f(x + H) - f(x - H)) / (2.0 * H)
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn scalar_forward_values() {
+        assert_close((SCALAR_SIGMOID.forward)(0.0), 0.5);
+        assert_close(
+            (SCALAR_SIGMOID.forward)(2.0),
+            1.0 / (1.0 + (-2.0_f64).exp()),
+        );
+        assert_close((SCALAR_TANH.forward)(0.5), 0.5_f64.tanh());
+        assert_close((SCALAR_TANH.forward)(-1.5), (-1.5_f64).tanh());
+        assert_close((SCALAR_RELU.forward)(1.5), 1.5);
+        assert_close((SCALAR_RELU.forward)(-1.5), 0.0);
+    }
+
+    #[test]
+    fn scalar_derivatives_match_finite_differences() {
+        for x in [-2.0, -0.5, 0.3, 1.7] {
+            for f in [SCALAR_SIGMOID, SCALAR_TANH, SCALAR_RELU] {
+                assert_close((f.backward)(x), numeric_derivative(f.forward, x));
+            }
+        }
+    }
+
+    #[test]
+    fn forward_maps_every_value() {
+        let sum = Matrix::from(&vec![0.0, 2.0]);
+        assert_eq!((RELU_ACTIVATION.forward)(&sum).items, vec![0.0, 2.0]);
+        assert_eq!((LINEAR_ACTIVATION.forward)(&sum).items, vec![0.0, 2.0]);
+    }
+
+    #[test]
+    fn softmax_sums_to_one() {
+        let sum = Matrix::from(&vec![1.0, 2.0, 3.0]);
+        let result = (SOFTMAX_ACTIVATION.forward)(&sum);
+        let total: f64 = result.items.iter().sum();
+        assert_close(total, 1.0);
+        assert!(result.get(2, 0) > result.get(1, 0));
+        assert!(result.get(1, 0) > result.get(0, 0));
+
+        let denominator = 1_f64.exp() + 2_f64.exp() + 3_f64.exp();
+        assert_close(result.get(0, 0), 1_f64.exp() / denominator);
+    }
+
+    #[test]
+    fn softmax_is_stable_for_large_inputs() {
+        let sum = Matrix::from(&vec![1000.0, 1000.0]);
+        let result = (SOFTMAX_ACTIVATION.forward)(&sum);
+        assert_eq!(result.items, vec![0.5, 0.5]);
+    }
+
+    #[test]
+    fn softmax_backward_with_cross_entropy() {
+        let sum = Matrix::from(&vec![0.5, -1.0, 2.0]);
+        let activated = (SOFTMAX_ACTIVATION.forward)(&sum);
+        let expected = Matrix::from(&vec![0.0, 1.0, 0.0]);
+        let gradient = (crate::error::CE_LOSS.grad)(&activated, &expected);
+        let result = (SOFTMAX_ACTIVATION.backward)(&sum, &activated, &gradient);
+        for i in 0..3 {
+            assert_close(result.get(i, 0), activated.get(i, 0) - expected.get(i, 0));
+        }
+    }
+}
@@ -0,0 +1,607 @@
+use std::fmt;
+
+use crate::activation::ActivationFunctionKey;
+use crate::conv::{Conv2d, Window};
+use crate::error::ErrorFunctionKey;
+use crate::initializer::InitializerKey;
+use crate::layer::{Dense, Layer, LayerKey, Shape};
+use crate::network::{create_network, Network};
+use crate::pooling::{Pool2d, PoolKey};
+use crate::regularizer::RegularizerKey;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildError {
+    ZeroInputs,
+    MissingLoss,
+    NoLayers,
+    /// A layer option such as `activation` was set before any `dense` call.
+    NoLayerToConfigure(&'static str),
+    InvalidLayer {
+        layer: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::ZeroInputs => write!(f, "input dimension must be positive"),
+            BuildError::MissingLoss => write!(f, "no loss function set"),
+            BuildError::NoLayers => write!(f, "network has no layers"),
+            BuildError::NoLayerToConfigure(option) => {
+                write!(f, "{} set before the first dense layer", option)
+            }
+            BuildError::InvalidLayer { layer, message } => {
+                write!(f, "layer {}: {}", layer, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// A layer as described to the builder. `units` counts dense units or
+/// convolution filters and is 0 for other layers.
+pub(crate) struct LayerSpec {
+    pub(crate) key: LayerKey,
+    pub(crate) units: u32,
+    pub(crate) activation: ActivationFunctionKey,
+    pub(crate) initializer: Option<InitializerKey>,
+    pub(crate) regularizer: Option<RegularizerKey>,
+    pub(crate) dropout: f64,
+}
+
+impl LayerSpec {
+    pub(crate) fn new(key: LayerKey, units: u32, activation: ActivationFunctionKey) -> LayerSpec {
+        LayerSpec {
+            key,
+            units,
+            activation,
+            initializer: None,
+            regularizer: None,
+            dropout: 0.0,
+        }
+    }
+}
+
+/// Describes a network layer by layer and builds it with initialized
+/// weights. Options such as `activation` or `dropout` apply to the most
+/// recently added layer; dense layers default to sigmoid and convolutions
+/// to ReLU.
+pub struct NetworkBuilder {
+    input: Shape,
+    loss: Option<ErrorFunctionKey>,
+    seed: Option<u64>,
+    layers: Vec<LayerSpec>,
+    error: Option<BuildError>,
+}
+
+impl NetworkBuilder {
+    pub fn new(input_dim: usize) -> NetworkBuilder {
+        NetworkBuilder::image(input_dim, 1, 1)
+    }
+
+    /// A network on `channels` planes of `height x width` values, stored
+    /// channel by channel, row by row.
+    pub fn image(channels: usize, height: usize, width: usize) -> NetworkBuilder {
+        NetworkBuilder {
+            input: Shape {
+                channels,
+                height,
+                width,
+            },
+            loss: None,
+            seed: None,
+            layers: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// A builder over already described layers, e.g. read from a file.
+    pub(crate) fn from_layers(
+        input: Shape,
+        loss: ErrorFunctionKey,
+        layers: Vec<LayerSpec>,
+    ) -> NetworkBuilder {
+        NetworkBuilder {
+            input,
+            loss: Some(loss),
+            seed: None,
+            layers,
+            error: None,
+        }
+    }
+
+    pub fn loss(mut self, loss: ErrorFunctionKey) -> NetworkBuilder {
+        self.loss = Some(loss);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> NetworkBuilder {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn dense(self, units: u32) -> NetworkBuilder {
+        self.push(LayerKey::Dense, units, ActivationFunctionKey::Sigmoid)
+    }
+
+    /// A 2D convolution with `filters` output channels and square
+    /// `kernel x kernel` kernels, using a stride of 1 and no padding unless
+    /// configured otherwise.
+    pub fn conv2d(self, filters: u32, kernel: usize) -> NetworkBuilder {
+        let window = Window {
+            kernel,
+            stride: 1,
+            padding: 0,
+        };
+        self.push(
+            LayerKey::Conv2d(window),
+            filters,
+            ActivationFunctionKey::ReLu,
+        )
+    }
+
+    pub fn flatten(self) -> NetworkBuilder {
+        self.push(LayerKey::Flatten, 0, ActivationFunctionKey::Linear)
+    }
+
+    /// Max pooling over `size x size` windows, with a stride of `size`
+    /// unless configured otherwise.
+    pub fn max_pool2d(self, size: usize) -> NetworkBuilder {
+        self.pool2d(PoolKey::Max, size)
+    }
+
+    /// Average pooling over `size x size` windows, with a stride of `size`
+    /// unless configured otherwise.
+    pub fn avg_pool2d(self, size: usize) -> NetworkBuilder {
+        self.pool2d(PoolKey::Average, size)
+    }
+
+    fn pool2d(self, pool: PoolKey, size: usize) -> NetworkBuilder {
+        let window = Window {
+            kernel: size,
+            stride: size,
+            padding: 0,
+        };
+        self.push(
+            LayerKey::Pool2d(pool, window),
+            0,
+            ActivationFunctionKey::Linear,
+        )
+    }
+
+    /// Averages each channel to one value, so dense layers can follow
+    /// without a flatten layer.
+    pub fn global_avg_pool(self) -> NetworkBuilder {
+        self.push(LayerKey::GlobalAvgPool, 0, ActivationFunctionKey::Linear)
+    }
+
+    fn push(
+        mut self,
+        key: LayerKey,
+        units: u32,
+        activation: ActivationFunctionKey,
+    ) -> NetworkBuilder {
+        self.layers.push(LayerSpec::new(key, units, activation));
+        self
+    }
+
+    pub fn activation(self, activation: ActivationFunctionKey) -> NetworkBuilder {
+        self.configure("activation", |layer| layer.activation = activation)
+    }
+
+    pub fn initializer(mut self, initializer: InitializerKey) -> NetworkBuilder {
+        if !initializer.is_valid() && !self.layers.is_empty() && self.error.is_none() {
+            self.error = Some(BuildError::InvalidLayer {
+                layer: self.layers.len() - 1,
+                message: format!("invalid initializer {:?}", initializer),
+            });
+        }
+        self.configure("initializer", |layer| layer.initializer = Some(initializer))
+    }
+
+    pub fn regularizer(self, regularizer: RegularizerKey) -> NetworkBuilder {
+        self.configure("regularizer", |layer| layer.regularizer = Some(regularizer))
+    }
+
+    /// Drops each output of the current layer with probability `rate`
+    /// during training, through a dropout layer placed after it.
+    pub fn dropout(self, rate: f64) -> NetworkBuilder {
+        self.configure("dropout", |layer| layer.dropout = rate)
+    }
+
+    pub fn stride(self, stride: usize) -> NetworkBuilder {
+        self.configure_window("stride", true, |window| window.stride = stride)
+    }
+
+    /// Zero padding added on every side of the input of a convolution.
+    pub fn padding(self, padding: usize) -> NetworkBuilder {
+        self.configure_window("padding", false, |window| window.padding = padding)
+    }
+
+    fn configure_window<F: FnOnce(&mut Window)>(
+        mut self,
+        option: &'static str,
+        pooling: bool,
+        f: F,
+    ) -> NetworkBuilder {
+        let layer = self.layers.len().wrapping_sub(1);
+        let error = match self.layers.last_mut().map(|layer| &mut layer.key) {
+            Some(LayerKey::Conv2d(window)) => {
+                f(window);
+                return self;
+            }
+            Some(LayerKey::Pool2d(_, window)) if pooling => {
+                f(window);
+                return self;
+            }
+            Some(_) => BuildError::InvalidLayer {
+                layer,
+                message: if pooling {
+                    format!("{} only applies to convolution and pooling layers", option)
+                } else {
+                    format!("{} only applies to convolution layers", option)
+                },
+            },
+            None => BuildError::NoLayerToConfigure(option),
+        };
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+        self
+    }
+
+    fn configure<F: FnOnce(&mut LayerSpec)>(
+        mut self,
+        option: &'static str,
+        f: F,
+    ) -> NetworkBuilder {
+        match self.layers.last_mut() {
+            Some(layer) => f(layer),
+            None => {
+                if self.error.is_none() {
+                    self.error = Some(BuildError::NoLayerToConfigure(option));
+                }
+            }
+        }
+        self
+    }
+
+    pub fn build(self) -> Result<Network, BuildError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        if self.input.size() == 0 {
+            return Err(BuildError::ZeroInputs);
+        }
+        let loss = self.loss.ok_or(BuildError::MissingLoss)?;
+        if self.layers.is_empty() {
+            return Err(BuildError::NoLayers);
+        }
+        let mut shape = self.input;
+        for (index, layer) in self.layers.iter().enumerate() {
+            shape = validate_layer(layer, shape, index == self.layers.len() - 1, loss).map_err(
+                |message| BuildError::InvalidLayer {
+                    layer: index,
+                    message,
+                },
+            )?;
+        }
+
+        let mut network = create_network(loss);
+        if let Some(seed) = self.seed {
+            network.seed(seed);
+        }
+        for spec in &self.layers {
+            match spec.key {
+                LayerKey::Dense => {
+                    let mut layer = Dense::new(spec.units as usize, spec.activation);
+                    if let Some(initializer) = spec.initializer {
+                        layer.initializer = initializer;
+                    }
+                    layer.regularizer = spec.regularizer;
+                    network.add_layer(layer);
+                }
+                LayerKey::Conv2d(window) => {
+                    let mut layer = Conv2d::new(spec.units as usize, window, spec.activation);
+                    if let Some(initializer) = spec.initializer {
+                        layer.initializer = initializer;
+                    }
+                    layer.regularizer = spec.regularizer;
+                    network.add_layer(layer);
+                }
+                LayerKey::Flatten => network.add_flatten(),
+                LayerKey::Pool2d(pool, window) => network.add_pool2d(pool, window),
+                LayerKey::GlobalAvgPool => network.add_global_avg_pool(),
+                LayerKey::Dropout(rate) => network.add_dropout(rate),
+            }
+            if spec.dropout > 0.0 {
+                network.add_dropout(spec.dropout);
+            }
+        }
+        network.initialize_with_shape(self.input, None);
+        Ok(network)
+    }
+}
+
+/// Checks a layer on `input` and returns its output shape.
+fn validate_layer(
+    layer: &LayerSpec,
+    input: Shape,
+    is_output: bool,
+    loss: ErrorFunctionKey,
+) -> Result<Shape, String> {
+    let output = match layer.key {
+        LayerKey::Dense => {
+            if layer.units == 0 {
+                return Err("needs at least one unit".to_string());
+            }
+            if !input.is_flat() {
+                return Err(format!("input {} is not flat, add a flatten layer", input));
+            }
+            Shape::flat(layer.units as usize)
+        }
+        LayerKey::Conv2d(window) => {
+            if layer.units == 0 {
+                return Err("needs at least one filter".to_string());
+            }
+            window
+                .output_shape(input, layer.units as usize)
+                .ok_or_else(|| format!("{:?} does not fit input {}", window, input))?
+        }
+        LayerKey::Flatten
+        | LayerKey::Pool2d(..)
+        | LayerKey::GlobalAvgPool
+        | LayerKey::Dropout(_) => {
+            if is_output {
+                return Err(format!("{:?} can not be the output layer", layer.key));
+            }
+            if layer.activation != ActivationFunctionKey::Linear
+                || layer.initializer.is_some()
+                || layer.regularizer.is_some()
+            {
+                return Err(format!(
+                    "{:?} has no activation or weights to configure",
+                    layer.key
+                ));
+            }
+            match layer.key {
+                LayerKey::Pool2d(pool, window) => Pool2d::new(pool, window).output_shape(input)?,
+                LayerKey::GlobalAvgPool => Shape::flat(input.channels),
+                LayerKey::Dropout(rate) => {
+                    if !(0.0..1.0).contains(&rate) {
+                        return Err(format!("dropout rate {} is not in [0, 1)", rate));
+                    }
+                    input
+                }
+                _ => Shape::flat(input.size()),
+            }
+        }
+    };
+    if !(0.0..1.0).contains(&layer.dropout) {
+        return Err(format!("dropout rate {} is not in [0, 1)", layer.dropout));
+    }
+    if is_output && layer.dropout > 0.0 {
+        return Err("dropout can not be used on the output layer".to_string());
+    }
+    if let Some(regularizer) = layer.regularizer {
+        if !regularizer.is_valid() {
+            return Err(format!(
+                "negative regularization strength {:?}",
+                regularizer
+            ));
+        }
+    }
+    if let ActivationFunctionKey::Softmax = layer.activation {
+        if !is_output {
+            return Err("softmax is only supported on the output layer".to_string());
+        }
+        if loss != ErrorFunctionKey::CrossEntropy {
+            return Err("softmax requires the cross-entropy loss".to_string());
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::DataSetItem;
+
+    fn classifier() -> NetworkBuilder {
+        NetworkBuilder::new(3)
+            .loss(ErrorFunctionKey::CrossEntropy)
+            .seed(4)
+            .dense(5)
+            .activation(ActivationFunctionKey::ReLu)
+            .regularizer(RegularizerKey::L2(0.01))
+            .dropout(0.5)
+            .dense(4)
+            .activation(ActivationFunctionKey::TanH)
+            .initializer(InitializerKey::Constant(0.25))
+            .dense(2)
+            .activation(ActivationFunctionKey::Softmax)
+    }
+
+    #[test]
+    fn builds_configured_layers() {
+        let network = classifier().build().unwrap();
+        let shapes: Vec<(usize, usize)> = network
+            .layers
+            .iter()
+            .map(|l| (l.parameters().rows, l.parameters().cols))
+            .collect();
+        assert_eq!(shapes, vec![(5, 4), (0, 0), (4, 6), (2, 5)]);
+        assert_eq!(network.layers[1].key(), Some(LayerKey::Dropout(0.5)));
+        assert_eq!(
+            network.layers[0].regularizer(),
+            Some(RegularizerKey::L2(0.01))
+        );
+        assert_eq!(network.layers[2].parameters().get(0, 1), 0.25);
+        assert_eq!(
+            network.layers[3].activation(),
+            Some(ActivationFunctionKey::Softmax)
+        );
+        assert_eq!(network.input_dim(), 3);
+    }
+
+    #[test]
+    fn seeded_builds_are_reproducible() {
+        let a = classifier().build().unwrap();
+        let b = classifier().build().unwrap();
+        assert_eq!(a.layers[0].parameters(), b.layers[0].parameters());
+    }
+
+    #[test]
+    fn dropout_only_affects_training() {
+        let network = classifier().build().unwrap();
+        let batch = [DataSetItem {
+            input: vec![0.5, -1.0, 2.0],
+            output: vec![1.0, 0.0],
+        }];
+        let first = network.compute_gradients(&batch);
+        let second = network.compute_gradients(&batch);
+        assert_ne!(first.gradients[0], second.gradients[0]);
+
+        let prediction = |n: &Network| n.predict(&batch[0].input);
+        assert_eq!(prediction(&network), prediction(&network));
+    }
+
+    #[test]
+    fn builds_convolutions() {
+        let network = NetworkBuilder::image(1, 6, 6)
+            .loss(ErrorFunctionKey::CrossEntropy)
+            .conv2d(4, 3)
+            .padding(1)
+            .conv2d(2, 2)
+            .stride(2)
+            .flatten()
+            .dense(3)
+            .activation(ActivationFunctionKey::Softmax)
+            .build()
+            .unwrap();
+        let shapes: Vec<String> = network
+            .summary()
+            .layers
+            .iter()
+            .map(|l| l.output.to_string())
+            .collect();
+        assert_eq!(shapes, vec!["4x6x6", "2x3x3", "18", "3"]);
+        assert_eq!(
+            network.layers[0].activation(),
+            Some(ActivationFunctionKey::ReLu)
+        );
+        assert_eq!(network.predict(&[0.5; 36]).len(), 3);
+    }
+
+    #[test]
+    fn builds_pooling_layers() {
+        let network = NetworkBuilder::image(2, 8, 8)
+            .loss(ErrorFunctionKey::CrossEntropy)
+            .conv2d(4, 3)
+            .padding(1)
+            .max_pool2d(2)
+            .avg_pool2d(2)
+            .stride(1)
+            .global_avg_pool()
+            .dense(2)
+            .activation(ActivationFunctionKey::Softmax)
+            .build()
+            .unwrap();
+        let shapes: Vec<String> = network
+            .summary()
+            .layers
+            .iter()
+            .map(|l| l.output.to_string())
+            .collect();
+        assert_eq!(shapes, vec!["4x8x8", "4x4x4", "4x3x3", "4", "2"]);
+    }
+
+    #[test]
+    fn rejects_invalid_convolutions() {
+        let error = |builder: NetworkBuilder| builder.build().err().unwrap();
+        let base = || NetworkBuilder::image(1, 4, 4).loss(ErrorFunctionKey::MeanSquared);
+
+        assert_eq!(
+            error(base().dense(2).stride(2)),
+            BuildError::InvalidLayer {
+                layer: 0,
+                message: "stride only applies to convolution and pooling layers".to_string()
+            }
+        );
+        for builder in [
+            base().dense(1),
+            base().conv2d(2, 5).flatten().dense(1),
+            base().conv2d(0, 3).flatten().dense(1),
+            base()
+                .conv2d(2, 3)
+                .flatten()
+                .activation(ActivationFunctionKey::ReLu)
+                .dense(1),
+            base().conv2d(2, 3).flatten(),
+            base().max_pool2d(8).flatten().dense(1),
+            base().max_pool2d(2).padding(1).flatten().dense(1),
+            base()
+                .avg_pool2d(2)
+                .regularizer(RegularizerKey::L2(0.1))
+                .flatten()
+                .dense(1),
+            base().global_avg_pool(),
+        ] {
+            assert!(matches!(error(builder), BuildError::InvalidLayer { .. }));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_configurations() {
+        let error = |builder: NetworkBuilder| builder.build().err().unwrap();
+        let base = || NetworkBuilder::new(2).loss(ErrorFunctionKey::MeanSquared);
+
+        assert_eq!(
+            error(NetworkBuilder::new(2).dense(1)),
+            BuildError::MissingLoss
+        );
+        assert_eq!(error(base()), BuildError::NoLayers);
+        assert_eq!(
+            error(
+                NetworkBuilder::new(0)
+                    .loss(ErrorFunctionKey::LogLoss)
+                    .dense(1)
+            ),
+            BuildError::ZeroInputs
+        );
+        assert_eq!(
+            error(base().dropout(0.5).dense(1)),
+            BuildError::NoLayerToConfigure("dropout")
+        );
+        for builder in [
+            base().dense(0),
+            base().dense(3).dropout(1.0).dense(1),
+            base().dense(1).dropout(0.1),
+            base().dense(2).activation(ActivationFunctionKey::Softmax),
+            base()
+                .dense(2)
+                .regularizer(RegularizerKey::L1(-0.1))
+                .dense(1),
+            base()
+                .dense(2)
+                .initializer(InitializerKey::Uniform(0.0))
+                .dense(1),
+        ] {
+            assert!(matches!(error(builder), BuildError::InvalidLayer { .. }));
+        }
+        assert_eq!(
+            error(
+                NetworkBuilder::new(2)
+                    .loss(ErrorFunctionKey::MeanSquared)
+                    .dense(2)
+                    .activation(ActivationFunctionKey::Softmax)
+            ),
+            BuildError::InvalidLayer {
+                layer: 0,
+                message: "softmax requires the cross-entropy loss".to_string(),
+            }
+        );
+    }
+}
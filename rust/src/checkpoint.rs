@@ -0,0 +1,1198 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::activation::ActivationFunctionKey;
+use crate::builder::{LayerSpec, NetworkBuilder};
+use crate::conv::Window;
+use crate::data_loader::DataLoader;
+use crate::early_stopping::{EarlyStopping, EarlyStoppingState, PlateauState, ReduceLrOnPlateau};
+use crate::error::ErrorFunctionKey;
+use crate::history::{HistoryEntry, TrainingHistory};
+use crate::layer::{LayerKey, Shape};
+use crate::matrix::Matrix;
+use crate::network::Network;
+use crate::optimizer::{Optimizer, OptimizerState};
+use crate::pooling::PoolKey;
+use crate::random::SeededRng;
+use crate::regularizer::RegularizerKey;
+use crate::validation::Evaluation;
+
+/// Written as `v2` after the tag of the first line; version 1 files have no
+/// version field. Version 2 added the controller and history sections.
+const CHECKPOINT_VERSION: u32 = 2;
+/// Version 2 describes every kind of layer; version 1 only had dense layers.
+const NETWORK_VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    Format(String),
+    /// The checkpoint was taken from a different network, optimizer or
+    /// dataset than the one it is restored into.
+    Mismatch(String),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "{}", e),
+            CheckpointError::Format(message) => write!(f, "invalid saved state: {}", message),
+            CheckpointError::Mismatch(message) => {
+                write!(f, "checkpoint does not match: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl From<io::Error> for CheckpointError {
+    fn from(e: io::Error) -> CheckpointError {
+        CheckpointError::Io(e)
+    }
+}
+
+/// Everything needed to continue a training run exactly: weights, optimizer
+/// state, the iteration counter, the RNG states and the data loader order.
+/// Checkpoints are taken between epochs, after the controllers have seen the
+/// epoch's evaluation, and can carry their state and the training history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub iteration: u64,
+    pub weights: Vec<Matrix>,
+    pub optimizer: OptimizerState,
+    pub rng: [u64; 4],
+    pub dropout_rng: [u64; 4],
+    pub data_order: Vec<usize>,
+    pub plateau: Option<PlateauState>,
+    pub early_stopping: Option<EarlyStoppingState>,
+    pub history: Option<TrainingHistory>,
+}
+
+impl Checkpoint {
+    pub fn capture(
+        network: &Network,
+        optimizer: &dyn Optimizer,
+        loader: &DataLoader,
+        iteration: u64,
+    ) -> Checkpoint {
+        Checkpoint {
+            iteration,
+            weights: network
+                .layers
+                .iter()
+                .map(|l| l.parameters().clone())
+                .collect(),
+            optimizer: optimizer.state(),
+            rng: network.rng.state(),
+            dropout_rng: network.dropout_rng_state(),
+            data_order: loader.order().to_vec(),
+            plateau: None,
+            early_stopping: None,
+            history: None,
+        }
+    }
+
+    pub fn with_plateau(mut self, plateau: &ReduceLrOnPlateau) -> Checkpoint {
+        self.plateau = Some(plateau.state());
+        self
+    }
+
+    pub fn with_early_stopping(mut self, early_stopping: &EarlyStopping) -> Checkpoint {
+        self.early_stopping = Some(early_stopping.state());
+        self
+    }
+
+    pub fn with_history(mut self, history: &TrainingHistory) -> Checkpoint {
+        self.history = Some(history.clone());
+        self
+    }
+
+    /// Puts the saved state back into an initialized network of the same
+    /// architecture, a fresh optimizer of the same kind and a loader over the
+    /// original dataset. Returns the iteration to continue from, or a
+    /// `Mismatch` without changing anything if the checkpoint does not fit.
+    pub fn restore(
+        &self,
+        network: &mut Network,
+        optimizer: &mut dyn Optimizer,
+        loader: &mut DataLoader,
+    ) -> Result<u64, CheckpointError> {
+        if network.layers.len() != self.weights.len() {
+            return Err(mismatch(format!(
+                "{} layers but the network has {}",
+                self.weights.len(),
+                network.layers.len()
+            )));
+        }
+        for (index, (layer, weights)) in network.layers.iter().zip(&self.weights).enumerate() {
+            let current = layer.parameters();
+            if (current.rows, current.cols) != (weights.rows, weights.cols) {
+                return Err(mismatch(format!(
+                    "weights of layer {} are {}x{} but the layer has {}x{}",
+                    index, weights.rows, weights.cols, current.rows, current.cols
+                )));
+            }
+        }
+        if !self
+            .optimizer
+            .buffers
+            .iter()
+            .all(|b| same_shapes(b, &self.weights))
+        {
+            return Err(mismatch(
+                "optimizer state does not match the weights".to_string(),
+            ));
+        }
+        if let Some(state) = &self.early_stopping {
+            if !state.best_weights.is_empty() && !same_shapes(&state.best_weights, &self.weights) {
+                return Err(mismatch(
+                    "best weights of early stopping do not match the weights".to_string(),
+                ));
+            }
+        }
+        if !is_permutation(&self.data_order, loader.items().len()) {
+            return Err(mismatch(format!(
+                "data order is not a permutation of {} items",
+                loader.items().len()
+            )));
+        }
+
+        optimizer
+            .set_state(self.optimizer.clone())
+            .map_err(mismatch)?;
+        for (layer, weights) in network.layers.iter_mut().zip(&self.weights) {
+            layer.set_parameters(weights.clone());
+        }
+        network.rng = SeededRng::from_state(self.rng);
+        network.set_dropout_rng_state(self.dropout_rng);
+        loader.set_order(&self.data_order);
+        Ok(self.iteration)
+    }
+
+    /// Puts the saved controller state and history back, where the
+    /// checkpoint has them. Call after `restore` has checked the checkpoint.
+    pub fn restore_progress(
+        &self,
+        plateau: &mut ReduceLrOnPlateau,
+        early_stopping: &mut EarlyStopping,
+        history: &mut TrainingHistory,
+    ) {
+        if let Some(state) = self.plateau {
+            plateau.set_state(state);
+        }
+        if let Some(state) = &self.early_stopping {
+            early_stopping.set_state(state.clone());
+        }
+        if let Some(saved) = &self.history {
+            *history = TrainingHistory::resume(&saved.optimizer, saved.took, saved.results.clone());
+        }
+    }
+
+    pub fn save<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "checkpoint\tv{}\t{}",
+            CHECKPOINT_VERSION, self.iteration
+        )?;
+        writeln!(writer, "{}", join(&self.rng))?;
+        writeln!(writer, "{}", join(&self.dropout_rng))?;
+        writeln!(writer, "{}", join(&self.data_order))?;
+        writeln!(writer, "weights\t{}", self.weights.len())?;
+        for matrix in &self.weights {
+            write_matrix(writer, matrix)?;
+        }
+        writeln!(
+            writer,
+            "optimizer\t{}\t{}",
+            self.optimizer.learning_rate_scale,
+            self.optimizer.buffers.len()
+        )?;
+        for buffer in &self.optimizer.buffers {
+            writeln!(writer, "buffer\t{}", buffer.len())?;
+            for matrix in buffer {
+                write_matrix(writer, matrix)?;
+            }
+        }
+        if let Some(state) = self.plateau {
+            writeln!(
+                writer,
+                "plateau\t{}\t{}\t{}",
+                optional(state.best),
+                state.wait,
+                state.cooldown_counter
+            )?;
+        }
+        if let Some(state) = &self.early_stopping {
+            writeln!(
+                writer,
+                "early_stopping\t{}\t{}",
+                optional(state.best),
+                state.wait
+            )?;
+            writeln!(writer, "weights\t{}", state.best_weights.len())?;
+            for matrix in &state.best_weights {
+                write_matrix(writer, matrix)?;
+            }
+        }
+        if let Some(history) = &self.history {
+            writeln!(
+                writer,
+                "history\t{}\t{}\t{}",
+                history.took,
+                history.results.len(),
+                history.optimizer
+            )?;
+            for entry in &history.results {
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}\t{}",
+                    entry.iteration,
+                    entry.train.loss,
+                    entry.train.accuracy,
+                    entry.test.loss,
+                    entry.test.accuracy
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load<R: BufRead>(reader: &mut R) -> Result<Checkpoint, CheckpointError> {
+        let (version, iteration) = match fields(&read_line(reader)?)[..] {
+            ["checkpoint", version, iteration] => (
+                parse_version(version, CHECKPOINT_VERSION)?,
+                parse(iteration)?,
+            ),
+            // Version 1 files have no version field.
+            ["checkpoint", iteration] => (1, parse(iteration)?),
+            _ => return Err(format_error("bad header")),
+        };
+
+        let rng = read_rng_state(reader)?;
+        let dropout_rng = read_rng_state(reader)?;
+        let data_order = parse_all(&read_line(reader)?)?;
+
+        let weights = read_matrices(reader, "weights")?;
+
+        let line = read_line(reader)?;
+        let (learning_rate_scale, count) = match fields(&line)[..] {
+            ["optimizer", scale, count] => (parse(scale)?, parse(count)?),
+            _ => return Err(format_error(&format!("bad optimizer line {:?}", line))),
+        };
+        let mut buffers = Vec::new();
+        for _ in 0..count {
+            buffers.push(read_matrices(reader, "buffer")?);
+        }
+
+        let mut checkpoint = Checkpoint {
+            iteration,
+            weights,
+            optimizer: OptimizerState {
+                learning_rate_scale,
+                buffers,
+            },
+            rng,
+            dropout_rng,
+            data_order,
+            plateau: None,
+            early_stopping: None,
+            history: None,
+        };
+        if version == 1 {
+            return Ok(checkpoint);
+        }
+        // The controller and history sections are optional.
+        while let Some(line) = read_optional_line(reader)? {
+            match fields(&line)[..] {
+                ["plateau", best, wait, cooldown_counter] => {
+                    checkpoint.plateau = Some(PlateauState {
+                        best: parse_optional(best)?,
+                        wait: parse(wait)?,
+                        cooldown_counter: parse(cooldown_counter)?,
+                    })
+                }
+                ["early_stopping", best, wait] => {
+                    checkpoint.early_stopping = Some(EarlyStoppingState {
+                        best: parse_optional(best)?,
+                        wait: parse(wait)?,
+                        best_weights: read_matrices(reader, "weights")?,
+                    })
+                }
+                ["history", took, count, ref optimizer @ ..] => {
+                    let count: usize = parse(count)?;
+                    let mut results = Vec::new();
+                    for _ in 0..count {
+                        results.push(read_history_entry(reader)?);
+                    }
+                    checkpoint.history = Some(TrainingHistory::resume(
+                        &optimizer.join("\t"),
+                        parse(took)?,
+                        results,
+                    ));
+                }
+                _ => return Err(format_error(&format!("bad section {:?}", line))),
+            }
+        }
+        Ok(checkpoint)
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.save(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Checkpoint, CheckpointError> {
+        Checkpoint::load(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+/// Writes the architecture and weights of an initialized network, which is
+/// enough to serve predictions or continue training with a fresh optimizer.
+/// Initializers are not stored since the weights are. Fails for
+/// user-defined layers, which can not be described.
+pub fn save_network<W: Write>(network: &Network, writer: &mut W) -> io::Result<()> {
+    // Check every layer first, so that a failure writes nothing.
+    let mut shape = network.input_shape();
+    let mut saved = Vec::with_capacity(network.layers.len());
+    for (index, layer) in network.layers.iter().enumerate() {
+        let output = match (layer.key(), layer.output_shape(shape)) {
+            (Some(key), Ok(output)) => {
+                saved.push((key, output));
+                output
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("layer {} ({}) can not be saved", index, layer.name()),
+                ))
+            }
+        };
+        shape = output;
+    }
+
+    writeln!(
+        writer,
+        "network\tv{}\t{:?}\t{}\t{}",
+        NETWORK_VERSION,
+        network.error(),
+        network.input_shape(),
+        network.layers.len()
+    )?;
+    for (layer, (key, output)) in network.layers.iter().zip(saved) {
+        let regularizer = match layer.regularizer() {
+            None => "none".to_string(),
+            Some(RegularizerKey::L1(l1)) => format!("L1\t{}", l1),
+            Some(RegularizerKey::L2(l2)) => format!("L2\t{}", l2),
+            Some(RegularizerKey::L1L2 { l1, l2 }) => format!("L1L2\t{}\t{}", l1, l2),
+        };
+        let activation = match layer.activation() {
+            Some(activation) => format!("{:?}", activation),
+            None => "none".to_string(),
+        };
+        match key {
+            LayerKey::Dense => writeln!(
+                writer,
+                "dense\t{}\t{}\t{}",
+                activation,
+                output.size(),
+                regularizer
+            )?,
+            LayerKey::Conv2d(window) => writeln!(
+                writer,
+                "conv2d\t{}\t{}\t{}\t{}\t{}\t{}",
+                activation,
+                output.channels,
+                window.kernel,
+                window.stride,
+                window.padding,
+                regularizer
+            )?,
+            LayerKey::Flatten => writeln!(writer, "flatten")?,
+            LayerKey::Pool2d(pool, window) => writeln!(
+                writer,
+                "pool2d\t{:?}\t{}\t{}\t{}",
+                pool, window.kernel, window.stride, window.padding
+            )?,
+            LayerKey::GlobalAvgPool => writeln!(writer, "global_avg_pool")?,
+            LayerKey::Dropout(rate) => writeln!(writer, "dropout\t{}", rate)?,
+        }
+        write_matrix(writer, layer.parameters())?;
+    }
+    Ok(())
+}
+
+pub fn load_network<R: BufRead>(reader: &mut R) -> Result<Network, CheckpointError> {
+    let line = read_line(reader)?;
+    let (version, error, input, count): (u32, ErrorFunctionKey, Shape, usize) =
+        match fields(&line)[..] {
+            ["network", version, error, input, count] => (
+                parse_version(version, NETWORK_VERSION)?,
+                parse_error_key(error)?,
+                parse_shape(input)?,
+                parse(count)?,
+            ),
+            // Version 1 files have no version field.
+            ["network", error, input, count] => (
+                1,
+                parse_error_key(error)?,
+                parse_shape(input)?,
+                parse(count)?,
+            ),
+            _ => return Err(format_error(&format!("bad header {:?}", line))),
+        };
+    if count == 0 {
+        return Err(format_error("network has no layers"));
+    }
+
+    let mut specs = Vec::new();
+    let mut parameters = Vec::new();
+    for _ in 0..count {
+        let line = read_line(reader)?;
+        let spec = match (version, &fields(&line)[..]) {
+            // Version 1 only had dense layers, with dropout as a layer option.
+            (1, ["layer", activation, units, rate, regularizer @ ..]) => {
+                let mut spec = LayerSpec::new(
+                    LayerKey::Dense,
+                    parse(units)?,
+                    parse_activation_key(activation)?,
+                );
+                spec.regularizer = parse_regularizer(regularizer)?;
+                spec.dropout = parse(rate)?;
+                spec
+            }
+            (1, _) => return Err(format_error(&format!("bad layer {:?}", line))),
+            (_, ["dense", activation, units, regularizer @ ..]) => {
+                let mut spec = LayerSpec::new(
+                    LayerKey::Dense,
+                    parse(units)?,
+                    parse_activation_key(activation)?,
+                );
+                spec.regularizer = parse_regularizer(regularizer)?;
+                spec
+            }
+            (_, ["conv2d", activation, filters, kernel, stride, padding, regularizer @ ..]) => {
+                let window = Window {
+                    kernel: parse(kernel)?,
+                    stride: parse(stride)?,
+                    padding: parse(padding)?,
+                };
+                let mut spec = LayerSpec::new(
+                    LayerKey::Conv2d(window),
+                    parse(filters)?,
+                    parse_activation_key(activation)?,
+                );
+                spec.regularizer = parse_regularizer(regularizer)?;
+                spec
+            }
+            (_, ["flatten"]) => LayerSpec::new(LayerKey::Flatten, 0, ActivationFunctionKey::Linear),
+            (_, ["pool2d", pool, kernel, stride, padding]) => {
+                let pool = match *pool {
+                    "Max" => PoolKey::Max,
+                    "Average" => PoolKey::Average,
+                    _ => return Err(format_error(&format!("unknown pooling {:?}", pool))),
+                };
+                let window = Window {
+                    kernel: parse(kernel)?,
+                    stride: parse(stride)?,
+                    padding: parse(padding)?,
+                };
+                LayerSpec::new(
+                    LayerKey::Pool2d(pool, window),
+                    0,
+                    ActivationFunctionKey::Linear,
+                )
+            }
+            (_, ["global_avg_pool"]) => {
+                LayerSpec::new(LayerKey::GlobalAvgPool, 0, ActivationFunctionKey::Linear)
+            }
+            (_, ["dropout", rate]) => LayerSpec::new(
+                LayerKey::Dropout(parse(rate)?),
+                0,
+                ActivationFunctionKey::Linear,
+            ),
+            _ => return Err(format_error(&format!("bad layer {:?}", line))),
+        };
+        parameters.push(read_matrix(reader)?);
+        // The builder places a dropout layer after the layer.
+        if spec.dropout > 0.0 {
+            parameters.push(Matrix::zeros(0, 0));
+        }
+        specs.push(spec);
+    }
+
+    // The same checks as for built networks, so nothing loads that the
+    // builder would reject.
+    let mut network = NetworkBuilder::from_layers(input, error, specs)
+        .build()
+        .map_err(|e| format_error(&format!("invalid network: {}", e)))?;
+    for (index, (layer, matrix)) in network.layers.iter_mut().zip(parameters).enumerate() {
+        let expected = layer.parameters();
+        if (matrix.rows, matrix.cols) != (expected.rows, expected.cols) {
+            return Err(format_error(&format!(
+                "layer {} ({}) needs {}x{} parameters, got {}x{}",
+                index,
+                layer.name(),
+                expected.rows,
+                expected.cols,
+                matrix.rows,
+                matrix.cols
+            )));
+        }
+        layer.set_parameters(matrix);
+    }
+    Ok(network)
+}
+
+/// Serializes the whole network before creating the file, so that an
+/// existing file is left alone when the network can not be saved.
+pub fn save_network_to_file<P: AsRef<Path>>(network: &Network, path: P) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    save_network(network, &mut buffer)?;
+    fs::write(path, buffer)
+}
+
+pub fn load_network_from_file<P: AsRef<Path>>(path: P) -> Result<Network, CheckpointError> {
+    load_network(&mut BufReader::new(File::open(path)?))
+}
+
+fn parse_version(value: &str, latest: u32) -> Result<u32, CheckpointError> {
+    match value.strip_prefix('v').map(str::parse) {
+        Some(Ok(version)) if (1..=latest).contains(&version) => Ok(version),
+        _ => Err(format_error(&format!("unsupported version {:?}", value))),
+    }
+}
+
+fn parse_error_key(name: &str) -> Result<ErrorFunctionKey, CheckpointError> {
+    match name {
+        "CrossEntropy" => Ok(ErrorFunctionKey::CrossEntropy),
+        "LogLoss" => Ok(ErrorFunctionKey::LogLoss),
+        "MeanSquared" => Ok(ErrorFunctionKey::MeanSquared),
+        _ => Err(format_error(&format!("unknown loss {:?}", name))),
+    }
+}
+
+fn parse_activation_key(name: &str) -> Result<ActivationFunctionKey, CheckpointError> {
+    match name {
+        "Sigmoid" => Ok(ActivationFunctionKey::Sigmoid),
+        "TanH" => Ok(ActivationFunctionKey::TanH),
+        "ReLu" => Ok(ActivationFunctionKey::ReLu),
+        "Softmax" => Ok(ActivationFunctionKey::Softmax),
+        "Linear" => Ok(ActivationFunctionKey::Linear),
+        _ => Err(format_error(&format!("unknown activation {:?}", name))),
+    }
+}
+
+/// A flat size such as `784` or `channels x height x width` like `1x28x28`.
+fn parse_shape(value: &str) -> Result<Shape, CheckpointError> {
+    let sizes: Vec<usize> = value.split('x').map(parse).collect::<Result<_, _>>()?;
+    match sizes[..] {
+        [size] => Ok(Shape::flat(size)),
+        [channels, height, width] => Ok(Shape {
+            channels,
+            height,
+            width,
+        }),
+        _ => Err(format_error(&format!("bad shape {:?}", value))),
+    }
+}
+
+fn parse_regularizer(fields: &[&str]) -> Result<Option<RegularizerKey>, CheckpointError> {
+    match fields {
+        ["none"] => Ok(None),
+        ["L1", l1] => Ok(Some(RegularizerKey::L1(parse(l1)?))),
+        ["L2", l2] => Ok(Some(RegularizerKey::L2(parse(l2)?))),
+        ["L1L2", l1, l2] => Ok(Some(RegularizerKey::L1L2 {
+            l1: parse(l1)?,
+            l2: parse(l2)?,
+        })),
+        _ => Err(format_error(&format!("bad regularizer {:?}", fields))),
+    }
+}
+
+fn read_rng_state<R: BufRead>(reader: &mut R) -> Result<[u64; 4], CheckpointError> {
+    let state: Vec<u64> = parse_all(&read_line(reader)?)?;
+    let state: [u64; 4] = state
+        .try_into()
+        .map_err(|_| format_error("RNG state needs 4 numbers"))?;
+    if state == [0; 4] {
+        return Err(format_error("RNG state is all zeros"));
+    }
+    Ok(state)
+}
+
+fn write_matrix<W: Write>(writer: &mut W, matrix: &Matrix) -> io::Result<()> {
+    writeln!(writer, "matrix\t{}\t{}", matrix.rows, matrix.cols)?;
+    writeln!(writer, "{}", join(&matrix.items))
+}
+
+fn read_matrices<R: BufRead>(reader: &mut R, tag: &str) -> Result<Vec<Matrix>, CheckpointError> {
+    let line = read_line(reader)?;
+    let count = match fields(&line)[..] {
+        [found, count] if found == tag => parse(count)?,
+        _ => return Err(format_error(&format!("expected {}, found {:?}", tag, line))),
+    };
+
+    // Not collected from `0..count`, which would reserve `count` entries
+    // before the file has shown that it holds them.
+    let mut matrices = Vec::new();
+    for _ in 0..count {
+        matrices.push(read_matrix(reader)?);
+    }
+    Ok(matrices)
+}
+
+fn read_matrix<R: BufRead>(reader: &mut R) -> Result<Matrix, CheckpointError> {
+    let line = read_line(reader)?;
+    let (rows, cols): (usize, usize) = match fields(&line)[..] {
+        ["matrix", rows, cols] => (parse(rows)?, parse(cols)?),
+        _ => return Err(format_error(&format!("bad matrix line {:?}", line))),
+    };
+    let items: Vec<f64> = parse_all(&read_line(reader)?)?;
+    if rows.checked_mul(cols) != Some(items.len()) {
+        return Err(format_error(&format!(
+            "{}x{} matrix has {} values",
+            rows,
+            cols,
+            items.len()
+        )));
+    }
+    Ok(Matrix { items, rows, cols })
+}
+
+fn read_history_entry<R: BufRead>(reader: &mut R) -> Result<HistoryEntry, CheckpointError> {
+    let line = read_line(reader)?;
+    match fields(&line)[..] {
+        [iteration, train_loss, train_accuracy, test_loss, test_accuracy] => Ok(HistoryEntry {
+            iteration: parse(iteration)?,
+            train: Evaluation {
+                loss: parse(train_loss)?,
+                accuracy: parse(train_accuracy)?,
+            },
+            test: Evaluation {
+                loss: parse(test_loss)?,
+                accuracy: parse(test_accuracy)?,
+            },
+        }),
+        _ => Err(format_error(&format!("bad history entry {:?}", line))),
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, CheckpointError> {
+    read_optional_line(reader)?.ok_or_else(|| format_error("unexpected end of input"))
+}
+
+/// The next line, or `None` at the end of the input.
+fn read_optional_line<R: BufRead>(reader: &mut R) -> Result<Option<String>, CheckpointError> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()))
+}
+
+fn fields(line: &str) -> Vec<&str> {
+    line.split('\t').collect()
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T, CheckpointError> {
+    value
+        .parse()
+        .map_err(|_| format_error(&format!("bad number {:?}", value)))
+}
+
+fn parse_all<T: std::str::FromStr>(line: &str) -> Result<Vec<T>, CheckpointError> {
+    line.split_whitespace().map(parse).collect()
+}
+
+/// `none` or a number.
+fn optional(value: Option<f64>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "none".to_string(),
+    }
+}
+
+fn parse_optional(value: &str) -> Result<Option<f64>, CheckpointError> {
+    match value {
+        "none" => Ok(None),
+        _ => parse(value).map(Some),
+    }
+}
+
+fn same_shapes(a: &[Matrix], b: &[Matrix]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(a, b)| (a.rows, a.cols) == (b.rows, b.cols))
+}
+
+fn format_error(message: &str) -> CheckpointError {
+    CheckpointError::Format(message.to_string())
+}
+
+fn mismatch(message: String) -> CheckpointError {
+    CheckpointError::Mismatch(message)
+}
+
+fn is_permutation(order: &[usize], len: usize) -> bool {
+    let mut seen = vec![false; len];
+    order.len() == len
+        && order
+            .iter()
+            .all(|&i| i < len && !std::mem::replace(&mut seen[i], true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activation::ActivationFunctionKey;
+    use crate::conv::Conv2d;
+    use crate::early_stopping::MonitorKey;
+    use crate::error::ErrorFunctionKey;
+    use crate::layer::{Dense, ForwardPassResult, Layer};
+    use crate::network::create_network;
+    use crate::optimizer::{sgd_optimizer, GradientDescentOptimizer};
+    use crate::schedule::Constant;
+    use crate::utils::DataSetItem;
+
+    fn dataset() -> Vec<DataSetItem> {
+        (0..12)
+            .map(|i| {
+                let x = i as f64 / 6.0 - 1.0;
+                DataSetItem {
+                    input: vec![x, x * x],
+                    output: vec![if x * x > 0.3 { 1.0 } else { 0.0 }],
+                }
+            })
+            .collect()
+    }
+
+    fn setup() -> (Network, GradientDescentOptimizer, DataLoader) {
+        let mut network = create_network(ErrorFunctionKey::LogLoss);
+        network.seed(5);
+        network.add(ActivationFunctionKey::TanH, 4);
+        network.add(ActivationFunctionKey::Sigmoid, 1);
+        network.initialize(2, None);
+
+        let mut optimizer = sgd_optimizer(Constant(0.5));
+        optimizer.momentum = 0.9;
+        (network, optimizer, DataLoader::new(dataset(), 4, true))
+    }
+
+    fn train(
+        network: &mut Network,
+        optimizer: &mut GradientDescentOptimizer,
+        loader: &mut DataLoader,
+        mut iteration: u64,
+        epochs: usize,
+    ) -> u64 {
+        for _ in 0..epochs {
+            for batch in loader.epoch(&mut network.rng) {
+                let data = network.compute_gradients(batch);
+                optimizer.do_update(&data, network, iteration);
+                iteration += 1;
+            }
+        }
+        iteration
+    }
+
+    #[test]
+    fn resumed_run_matches_uninterrupted_run() {
+        let (mut network, mut optimizer, mut loader) = setup();
+        let iteration = train(&mut network, &mut optimizer, &mut loader, 0, 3);
+        let checkpoint = Checkpoint::capture(&network, &optimizer, &loader, iteration);
+        train(&mut network, &mut optimizer, &mut loader, iteration, 3);
+
+        let mut buffer = Vec::new();
+        checkpoint.save(&mut buffer).unwrap();
+        let loaded = Checkpoint::load(&mut buffer.as_slice()).unwrap();
+        assert_eq!(loaded, checkpoint);
+
+        let (mut resumed, mut resumed_optimizer, mut resumed_loader) = setup();
+        let start = loaded
+            .restore(&mut resumed, &mut resumed_optimizer, &mut resumed_loader)
+            .unwrap();
+        assert_eq!(start, 9);
+        train(
+            &mut resumed,
+            &mut resumed_optimizer,
+            &mut resumed_loader,
+            start,
+            3,
+        );
+
+        for (a, b) in network.layers.iter().zip(&resumed.layers) {
+            assert_eq!(a.parameters(), b.parameters());
+        }
+        assert_eq!(network.rng, resumed.rng);
+    }
+
+    #[test]
+    fn restore_rejects_mismatched_checkpoints() {
+        let (mut network, mut optimizer, mut loader) = setup();
+        train(&mut network, &mut optimizer, &mut loader, 0, 1);
+        let checkpoint = Checkpoint::capture(&network, &optimizer, &loader, 3);
+
+        let mut wider = create_network(ErrorFunctionKey::LogLoss);
+        wider.add(ActivationFunctionKey::TanH, 5);
+        wider.add(ActivationFunctionKey::Sigmoid, 1);
+        wider.initialize(2, None);
+        let (_, mut fresh_optimizer, mut fresh_loader) = setup();
+        let before = wider.layers[0].parameters().clone();
+        let error = checkpoint
+            .restore(&mut wider, &mut fresh_optimizer, &mut fresh_loader)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "checkpoint does not match: weights of layer 0 are 4x3 but the layer has 5x3"
+        );
+        assert_eq!(wider.layers[0].parameters(), &before);
+        assert!(fresh_optimizer.state().buffers.is_empty());
+
+        let (mut fresh, mut fresh_optimizer, _) = setup();
+        let mut smaller_loader = DataLoader::new(dataset()[..6].to_vec(), 4, true);
+        assert!(matches!(
+            checkpoint.restore(&mut fresh, &mut fresh_optimizer, &mut smaller_loader),
+            Err(CheckpointError::Mismatch(_))
+        ));
+    }
+
+    #[test]
+    fn controllers_and_history_round_trip() {
+        let (mut network, mut optimizer, mut loader) = setup();
+        let iteration = train(&mut network, &mut optimizer, &mut loader, 0, 1);
+        let mut plateau = ReduceLrOnPlateau::new(MonitorKey::Loss, 0.5, 3);
+        let mut stopping = EarlyStopping::new(MonitorKey::Loss, 3, 0.0, true);
+        let mut history = TrainingHistory::new("SGD");
+        for loss in [0.5, 0.25, 0.3] {
+            let evaluation = Evaluation {
+                loss,
+                accuracy: 1.0 - loss,
+            };
+            history.record(iteration, evaluation, evaluation);
+            plateau.update(&evaluation, &mut optimizer);
+            stopping.update(&evaluation, &mut network);
+        }
+        let checkpoint = Checkpoint::capture(&network, &optimizer, &loader, iteration)
+            .with_plateau(&plateau)
+            .with_early_stopping(&stopping)
+            .with_history(&history);
+
+        let mut buffer = Vec::new();
+        checkpoint.save(&mut buffer).unwrap();
+        let loaded = Checkpoint::load(&mut buffer.as_slice()).unwrap();
+        assert_eq!(loaded, checkpoint);
+
+        let (mut resumed, mut resumed_optimizer, mut resumed_loader) = setup();
+        let mut resumed_plateau = ReduceLrOnPlateau::new(MonitorKey::Loss, 0.5, 3);
+        let mut resumed_stopping = EarlyStopping::new(MonitorKey::Loss, 3, 0.0, true);
+        let mut resumed_history = TrainingHistory::new("SGD");
+        loaded
+            .restore(&mut resumed, &mut resumed_optimizer, &mut resumed_loader)
+            .unwrap();
+        loaded.restore_progress(
+            &mut resumed_plateau,
+            &mut resumed_stopping,
+            &mut resumed_history,
+        );
+        assert_eq!(resumed_history, history);
+        assert_eq!(resumed_stopping.best(), Some(0.25));
+
+        // Both runs reduce the rate and stop at the same evaluation.
+        let worse = Evaluation {
+            loss: 0.4,
+            accuracy: 0.6,
+        };
+        for _ in 0..2 {
+            assert_eq!(
+                plateau.update(&worse, &mut optimizer),
+                resumed_plateau.update(&worse, &mut resumed_optimizer)
+            );
+            assert_eq!(
+                stopping.update(&worse, &mut network),
+                resumed_stopping.update(&worse, &mut resumed)
+            );
+        }
+        assert_eq!(optimizer.learning_rate_scale, 0.5);
+        assert_eq!(resumed_optimizer.learning_rate_scale, 0.5);
+        for (a, b) in network.layers.iter().zip(&resumed.layers) {
+            assert_eq!(a.parameters(), b.parameters());
+        }
+    }
+
+    #[test]
+    fn fresh_optimizer_state_round_trips() {
+        let (network, optimizer, loader) = setup();
+        let checkpoint = Checkpoint::capture(&network, &optimizer, &loader, 0);
+        assert!(checkpoint.optimizer.buffers.is_empty());
+
+        let mut buffer = Vec::new();
+        checkpoint.save(&mut buffer).unwrap();
+        assert_eq!(
+            Checkpoint::load(&mut buffer.as_slice()).unwrap(),
+            checkpoint
+        );
+    }
+
+    #[test]
+    fn network_round_trips_without_data() {
+        let mut network = create_network(ErrorFunctionKey::LogLoss);
+        network.add(ActivationFunctionKey::TanH, 4);
+        network.add_dropout(0.25);
+        let mut output = Dense::new(1, ActivationFunctionKey::Sigmoid);
+        output.regularizer = Some(RegularizerKey::L1L2 { l1: 0.1, l2: 0.01 });
+        network.add_layer(output);
+        network.initialize(2, None);
+
+        let mut buffer = Vec::new();
+        save_network(&network, &mut buffer).unwrap();
+        let loaded = load_network(&mut buffer.as_slice()).unwrap();
+
+        assert_eq!(loaded.input_dim(), 2);
+        assert_eq!(loaded.error(), ErrorFunctionKey::LogLoss);
+        assert_eq!(loaded.layers.len(), 3);
+        for (a, b) in network.layers.iter().zip(&loaded.layers) {
+            assert_eq!(a.key(), b.key());
+            assert_eq!(a.parameters(), b.parameters());
+            assert_eq!(a.activation(), b.activation());
+            assert_eq!(a.regularizer(), b.regularizer());
+        }
+        let item = &dataset()[3];
+        assert_eq!(network.predict(&item.input), loaded.predict(&item.input));
+    }
+
+    #[test]
+    fn convolutional_network_round_trips() {
+        let mut network = create_network(ErrorFunctionKey::CrossEntropy);
+        network.seed(2);
+        let window = Window {
+            kernel: 2,
+            stride: 2,
+            padding: 1,
+        };
+        let mut conv = Conv2d::new(3, window, ActivationFunctionKey::ReLu);
+        conv.regularizer = Some(RegularizerKey::L2(0.5));
+        network.add_layer(conv);
+        network.add_pool2d(
+            PoolKey::Max,
+            Window {
+                kernel: 2,
+                stride: 1,
+                padding: 0,
+            },
+        );
+        network.add_dropout(0.5);
+        network.add_flatten();
+        network.add(ActivationFunctionKey::Softmax, 2);
+        let shape = Shape {
+            channels: 2,
+            height: 3,
+            width: 3,
+        };
+        network.initialize_with_shape(shape, None);
+
+        let mut buffer = Vec::new();
+        save_network(&network, &mut buffer).unwrap();
+        let loaded = load_network(&mut buffer.as_slice()).unwrap();
+
+        assert_eq!(loaded.input_shape(), shape);
+        for (a, b) in network.layers.iter().zip(&loaded.layers) {
+            assert_eq!(a.key(), b.key());
+            assert_eq!(a.parameters(), b.parameters());
+            assert_eq!(a.regularizer(), b.regularizer());
+        }
+        let input: Vec<f64> = (0..18).map(|i| i as f64 / 18.0).collect();
+        assert_eq!(network.predict(&input), loaded.predict(&input));
+    }
+
+    #[test]
+    fn loads_version_1_networks() {
+        let text = "network\tLogLoss\t2\t2\n\
+                    layer\tTanH\t1\t0.25\tL2\t0.5\n\
+                    matrix\t1\t3\n0.1 0.2 0.3\n\
+                    layer\tSigmoid\t1\t0\tnone\n\
+                    matrix\t1\t2\n-0.5 2\n";
+        let network = load_network(&mut text.as_bytes()).unwrap();
+        let keys: Vec<Option<LayerKey>> = network.layers.iter().map(|l| l.key()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                Some(LayerKey::Dense),
+                Some(LayerKey::Dropout(0.25)),
+                Some(LayerKey::Dense)
+            ]
+        );
+        assert_eq!(
+            network.layers[0].regularizer(),
+            Some(RegularizerKey::L2(0.5))
+        );
+        assert_eq!(network.layers[2].parameters().items, vec![-0.5, 2.0]);
+
+        let versioned = text.replacen("network\t", "network\tv2\t", 1);
+        assert!(load_network(&mut versioned.as_bytes()).is_err());
+        let future = text.replacen("network\t", "network\tv3\t", 1);
+        match load_network(&mut future.as_bytes()) {
+            Err(CheckpointError::Format(message)) => {
+                assert_eq!(message, "unsupported version \"v3\"")
+            }
+            _ => panic!("expected an unsupported version"),
+        }
+    }
+
+    #[test]
+    fn load_rejects_networks_the_builder_rejects() {
+        let load = |layers: &str| {
+            let text = format!("network\tv2\tLogLoss\t2\t2\n{}", layers);
+            match load_network(&mut text.as_bytes()) {
+                Err(CheckpointError::Format(message)) => message,
+                _ => panic!("expected {:?} to be rejected", layers),
+            }
+        };
+        assert_eq!(
+            load(
+                "dense\tTanH\t0\tnone\nmatrix\t0\t3\n\n\
+                  dense\tSigmoid\t1\tnone\nmatrix\t1\t1\n0.5\n"
+            ),
+            "invalid network: layer 0: needs at least one unit"
+        );
+        assert_eq!(
+            load(
+                "dropout\t1\nmatrix\t0\t0\n\n\
+                  dense\tSigmoid\t1\tnone\nmatrix\t1\t3\n0.1 0.2 0.3\n"
+            ),
+            "invalid network: layer 0: dropout rate 1 is not in [0, 1)"
+        );
+    }
+
+    #[test]
+    fn loads_version_1_checkpoints() {
+        let (network, optimizer, loader) = setup();
+        let checkpoint = Checkpoint::capture(&network, &optimizer, &loader, 4);
+        let mut buffer = Vec::new();
+        checkpoint.save(&mut buffer).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        assert!(text.starts_with("checkpoint\tv2\t4\n"));
+
+        let old = text.replacen("checkpoint\tv2\t", "checkpoint\t", 1);
+        assert_eq!(Checkpoint::load(&mut old.as_bytes()).unwrap(), checkpoint);
+    }
+
+    struct Identity;
+
+    impl Layer for Identity {
+        fn name(&self) -> String {
+            "Identity".to_string()
+        }
+
+        fn output_shape(&self, input: Shape) -> Result<Shape, String> {
+            Ok(input)
+        }
+
+        fn forward(&self, input: &Matrix, _rng: Option<&mut SeededRng>) -> ForwardPassResult {
+            ForwardPassResult {
+                activated: input.clone(),
+                cache: Matrix::zeros(0, 0),
+            }
+        }
+
+        fn backward(
+            &self,
+            _input: &Matrix,
+            _result: &ForwardPassResult,
+            output_gradient: &Matrix,
+        ) -> Matrix {
+            output_gradient.clone()
+        }
+    }
+
+    #[test]
+    fn refuses_to_save_user_defined_layers() {
+        let mut network = create_network(ErrorFunctionKey::MeanSquared);
+        network.add(ActivationFunctionKey::TanH, 2);
+        network.add_layer(Identity);
+        network.initialize(2, None);
+        let mut buffer = Vec::new();
+        let error = save_network(&network, &mut buffer).unwrap_err();
+        assert_eq!(error.to_string(), "layer 1 (Identity) can not be saved");
+        assert!(buffer.is_empty());
+
+        let path = std::env::temp_dir().join("refuses_to_save_user_defined_layers.net");
+        fs::write(&path, "previous").unwrap();
+        assert!(save_network_to_file(&network, &path).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "previous");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_mismatched_network_weights() {
+        let (network, _, _) = setup();
+        let mut buffer = Vec::new();
+        save_network(&network, &mut buffer).unwrap();
+        let text = String::from_utf8(buffer)
+            .unwrap()
+            .replacen("TanH\t4", "TanH\t5", 1);
+        assert!(matches!(
+            load_network(&mut text.as_bytes()),
+            Err(CheckpointError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let (network, optimizer, loader) = setup();
+        let mut buffer = Vec::new();
+        Checkpoint::capture(&network, &optimizer, &loader, 0)
+            .save(&mut buffer)
+            .unwrap();
+        buffer.truncate(buffer.len() / 2);
+        assert!(matches!(
+            Checkpoint::load(&mut buffer.as_slice()),
+            Err(CheckpointError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_huge_counts() {
+        let (network, optimizer, loader) = setup();
+        let mut buffer = Vec::new();
+        Checkpoint::capture(&network, &optimizer, &loader, 0)
+            .save(&mut buffer)
+            .unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        let huge = usize::MAX.to_string();
+        for corrupt in [
+            text.replacen("weights\t2", &format!("weights\t{}", huge), 1),
+            text.replacen("matrix\t4\t3", &format!("matrix\t{}\t{}", huge, huge), 1),
+        ] {
+            assert_ne!(corrupt, text);
+            assert!(matches!(
+                Checkpoint::load(&mut corrupt.as_bytes()),
+                Err(CheckpointError::Format(_))
+            ));
+        }
+
+        let mut buffer = Vec::new();
+        save_network(&network, &mut buffer).unwrap();
+        let text =
+            String::from_utf8(buffer)
+                .unwrap()
+                .replacen("\t2\t2\n", &format!("\t2\t{}\n", huge), 1);
+        assert!(text.contains(&huge));
+        assert!(matches!(
+            load_network(&mut text.as_bytes()),
+            Err(CheckpointError::Format(_))
+        ));
+    }
+}
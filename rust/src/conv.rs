@@ -0,0 +1,360 @@
+use crate::activation::{activation_function, ActivationFN, ActivationFunctionKey};
+use crate::initializer::{default_initializer, initialize_weights, InitializerKey};
+use crate::layer::{ForwardPassResult, Layer, LayerKey, Shape};
+use crate::matrix::Matrix;
+use crate::random::SeededRng;
+use crate::regularizer::RegularizerKey;
+
+/// Kernel geometry of a 2D convolution. The input is zero-padded by
+/// `padding` on every side and the kernel moves `stride` steps at a time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window {
+    pub kernel: usize,
+    pub stride: usize,
+    pub padding: usize,
+}
+
+impl Window {
+    /// Output shape with `channels` channels, or `None` when the kernel does
+    /// not fit the padded input.
+    pub fn output_shape(&self, input: Shape, channels: usize) -> Option<Shape> {
+        let size = |length: usize| {
+            let padded = length + 2 * self.padding;
+            if self.kernel == 0 || self.stride == 0 || padded < self.kernel {
+                None
+            } else {
+                Some((padded - self.kernel) / self.stride + 1)
+            }
+        };
+        Some(Shape {
+            channels,
+            height: size(input.height)?,
+            width: size(input.width)?,
+        })
+    }
+
+    /// Input position read by kernel offset `k` at output position `o`, or
+    /// `None` inside the padding.
+    fn source(&self, o: usize, k: usize, length: usize) -> Option<usize> {
+        (o * self.stride + k)
+            .checked_sub(self.padding)
+            .filter(|&i| i < length)
+    }
+
+    /// Positions `y * width + x` within one channel of `shape` covered by
+    /// the window at output position (`oy`, `ox`), padding excluded.
+    pub fn field(&self, oy: usize, ox: usize, shape: Shape) -> Vec<usize> {
+        let mut positions = Vec::with_capacity(self.kernel * self.kernel);
+        for ky in 0..self.kernel {
+            if let Some(y) = self.source(oy, ky, shape.height) {
+                for kx in 0..self.kernel {
+                    if let Some(x) = self.source(ox, kx, shape.width) {
+                        positions.push(y * shape.width + x);
+                    }
+                }
+            }
+        }
+        positions
+    }
+
+    /// Unrolls every receptive field of `input` into a column (im2col). Row
+    /// 0 is all ones so that column 0 of the weights acts as the bias.
+    pub fn patches(&self, input: &Matrix, shape: Shape, output: Shape) -> Matrix {
+        let k = self.kernel;
+        let positions = output.height * output.width;
+        let mut patches = Matrix {
+            items: vec![0.0; (1 + shape.channels * k * k) * positions],
+            rows: 1 + shape.channels * k * k,
+            cols: positions,
+        };
+        for p in 0..positions {
+            patches.set(0, p, 1.0);
+        }
+        for c in 0..shape.channels {
+            for ky in 0..k {
+                for kx in 0..k {
+                    let row = 1 + (c * k + ky) * k + kx;
+                    for oy in 0..output.height {
+                        let y = match self.source(oy, ky, shape.height) {
+                            Some(y) => y,
+                            None => continue,
+                        };
+                        for ox in 0..output.width {
+                            if let Some(x) = self.source(ox, kx, shape.width) {
+                                let value = input.get((c * shape.height + y) * shape.width + x, 0);
+                                patches.set(row, oy * output.width + ox, value);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        patches
+    }
+
+    /// Adds the unrolled columns back onto the input positions they were
+    /// read from (col2im), the adjoint of `patches` without the bias row.
+    pub fn fold(&self, columns: &Matrix, shape: Shape, output: Shape) -> Matrix {
+        let k = self.kernel;
+        let mut result = vec![0.0; shape.size()];
+        for c in 0..shape.channels {
+            for ky in 0..k {
+                for kx in 0..k {
+                    let row = (c * k + ky) * k + kx;
+                    for oy in 0..output.height {
+                        let y = match self.source(oy, ky, shape.height) {
+                            Some(y) => y,
+                            None => continue,
+                        };
+                        for ox in 0..output.width {
+                            if let Some(x) = self.source(ox, kx, shape.width) {
+                                result[(c * shape.height + y) * shape.width + x] +=
+                                    columns.get(row, oy * output.width + ox);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Matrix::from(&result)
+    }
+}
+
+/// Views a column vector as `rows` rows, e.g. a channel-major feature map as
+/// one row per channel.
+pub fn reshape(vector: &Matrix, rows: usize) -> Matrix {
+    Matrix {
+        items: vector.items.clone(),
+        rows,
+        cols: vector.items.len() / rows,
+    }
+}
+
+/// 2D convolution with `filters` output channels, each spanning all input
+/// channels. The weights hold one row per filter, bias in column 0.
+pub struct Conv2d {
+    filters: usize,
+    window: Window,
+    pub initializer: InitializerKey,
+    pub regularizer: Option<RegularizerKey>,
+    input_shape: Shape,
+    output_shape: Shape,
+    weights: Matrix,
+    weights_transpose: Matrix,
+    activation_key: ActivationFunctionKey,
+    activation: ActivationFN,
+}
+
+impl Conv2d {
+    pub fn new(filters: usize, window: Window, activation: ActivationFunctionKey) -> Conv2d {
+        Conv2d {
+            filters,
+            window,
+            initializer: default_initializer(activation),
+            regularizer: None,
+            input_shape: Shape::flat(0),
+            output_shape: Shape::flat(0),
+            weights: Matrix::zeros(0, 0),
+            weights_transpose: Matrix::zeros(0, 0),
+            activation_key: activation,
+            activation: activation_function(activation),
+        }
+    }
+
+    /// Gradient with respect to the sums, one row per filter.
+    fn delta(&self, output_gradient: &Matrix) -> Matrix {
+        reshape(output_gradient, self.filters)
+    }
+}
+
+impl Layer for Conv2d {
+    fn name(&self) -> String {
+        format!(
+            "Conv2d {}x{}/{} pad {}",
+            self.window.kernel, self.window.kernel, self.window.stride, self.window.padding
+        )
+    }
+
+    fn output_shape(&self, input: Shape) -> Result<Shape, String> {
+        self.window
+            .output_shape(input, self.filters)
+            .ok_or_else(|| format!("{:?} does not fit input {}", self.window, input))
+    }
+
+    fn initialize(&mut self, input: Shape, rng: &mut SeededRng) {
+        self.input_shape = input;
+        self.output_shape = self
+            .output_shape(input)
+            .unwrap_or_else(|message| panic!("{}", message));
+        let cols = input.channels * self.window.kernel * self.window.kernel + 1;
+        let weights = initialize_weights(self.initializer, self.filters, cols, rng);
+        self.set_parameters(Matrix::from(&weights));
+    }
+
+    fn forward(&self, input: &Matrix, _rng: Option<&mut SeededRng>) -> ForwardPassResult {
+        let patches = self
+            .window
+            .patches(input, self.input_shape, self.output_shape);
+        let sum = self.weights.multiply(&patches);
+        let sum = reshape(&sum, sum.items.len());
+        ForwardPassResult {
+            activated: (self.activation.forward)(&sum),
+            cache: sum,
+        }
+    }
+
+    fn backward(
+        &self,
+        _input: &Matrix,
+        _result: &ForwardPassResult,
+        output_gradient: &Matrix,
+    ) -> Matrix {
+        let columns = self
+            .weights_transpose
+            .multiply(&self.delta(output_gradient));
+        self.window
+            .fold(&columns, self.input_shape, self.output_shape)
+    }
+
+    fn parameters(&self) -> &Matrix {
+        &self.weights
+    }
+
+    fn set_parameters(&mut self, parameters: Matrix) {
+        self.weights = parameters;
+        self.weights_transpose = self.weights.omit(0).transpose();
+    }
+
+    fn gradients(
+        &self,
+        input: &Matrix,
+        _result: &ForwardPassResult,
+        output_gradient: &Matrix,
+        total: &mut Matrix,
+    ) {
+        let patches = self
+            .window
+            .patches(input, self.input_shape, self.output_shape);
+        let delta = self.delta(output_gradient);
+        for i in 0..total.rows {
+            for j in 0..total.cols {
+                let mut diff = 0.0;
+                for p in 0..patches.cols {
+                    diff += delta.get(i, p) * patches.get(j, p);
+                }
+                let new_val = total.get(i, j) + diff;
+                total.set(i, j, new_val);
+            }
+        }
+    }
+
+    fn regularizer(&self) -> Option<RegularizerKey> {
+        self.regularizer
+    }
+
+    fn activation(&self) -> Option<ActivationFunctionKey> {
+        Some(self.activation_key)
+    }
+
+    fn key(&self) -> Option<LayerKey> {
+        Some(LayerKey::Conv2d(self.window))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(kernel: usize, stride: usize, padding: usize) -> Window {
+        Window {
+            kernel,
+            stride,
+            padding,
+        }
+    }
+
+    fn image(values: Vec<f64>) -> Matrix {
+        Matrix::from(&values)
+    }
+
+    #[test]
+    fn output_shapes() {
+        let input = Shape {
+            channels: 3,
+            height: 28,
+            width: 28,
+        };
+        let shape = |w: Window| w.output_shape(input, 8).map(|s| (s.height, s.width));
+        assert_eq!(shape(window(3, 1, 0)), Some((26, 26)));
+        assert_eq!(shape(window(3, 1, 1)), Some((28, 28)));
+        assert_eq!(shape(window(5, 2, 0)), Some((12, 12)));
+        assert_eq!(shape(window(29, 1, 0)), None);
+        assert_eq!(shape(window(3, 0, 0)), None);
+    }
+
+    #[test]
+    fn patches_unroll_receptive_fields() {
+        let shape = Shape {
+            channels: 1,
+            height: 3,
+            width: 3,
+        };
+        let w = window(2, 1, 0);
+        let output = w.output_shape(shape, 1).unwrap();
+        let input = image((1..=9).map(|x| x as f64).collect());
+        let patches = w.patches(&input, shape, output);
+        assert_eq!(
+            patches.to_arrays(),
+            vec![
+                vec![1.0, 1.0, 1.0, 1.0],
+                vec![1.0, 2.0, 4.0, 5.0],
+                vec![2.0, 3.0, 5.0, 6.0],
+                vec![4.0, 5.0, 7.0, 8.0],
+                vec![5.0, 6.0, 8.0, 9.0],
+            ]
+        );
+    }
+
+    #[test]
+    fn padding_reads_zeros() {
+        let shape = Shape {
+            channels: 1,
+            height: 2,
+            width: 2,
+        };
+        let w = window(3, 1, 1);
+        let output = w.output_shape(shape, 1).unwrap();
+        let patches = w.patches(&image(vec![1.0, 2.0, 3.0, 4.0]), shape, output);
+        // The centre of the kernel visits every input position once.
+        assert_eq!(patches.to_arrays()[5], vec![1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(patches.to_arrays()[1], vec![0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn fold_is_adjoint_of_patches() {
+        let shape = Shape {
+            channels: 2,
+            height: 4,
+            width: 3,
+        };
+        let w = window(2, 2, 1);
+        let output = w.output_shape(shape, 1).unwrap();
+        let x: Vec<f64> = (0..shape.size()).map(|i| (i as f64 * 0.7).sin()).collect();
+        let patches = w.patches(&image(x.clone()), shape, output);
+        let columns = patches.map(&|_, i, j| ((i * 5 + j) as f64).cos());
+
+        // <patches(x), c> == <x, fold(c)>, ignoring the bias row.
+        let lhs: f64 = (1..patches.rows)
+            .flat_map(|i| (0..patches.cols).map(move |j| (i, j)))
+            .map(|(i, j)| patches.get(i, j) * columns.get(i, j))
+            .sum();
+        let without_bias = Matrix {
+            items: columns.items[columns.cols..].to_vec(),
+            rows: columns.rows - 1,
+            cols: columns.cols,
+        };
+        let folded = w.fold(&without_bias, shape, output);
+        let rhs: f64 = x.iter().zip(&folded.items).map(|(a, b)| a * b).sum();
+        assert!((lhs - rhs).abs() < 1e-12, "{} != {}", lhs, rhs);
+    }
+}
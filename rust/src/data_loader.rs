@@ -0,0 +1,172 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::utils::DataSetItem;
+
+/// Owns a dataset and hands out non-overlapping minibatches. Items are
+/// shuffled in place once per epoch, so batches are borrowed slices rather
+/// than copies.
+pub struct DataLoader {
+    items: Vec<DataSetItem>,
+    /// Index of each item in the dataset the loader was created with.
+    positions: Vec<usize>,
+    pub batch_size: usize,
+    pub drop_last: bool,
+}
+
+impl DataLoader {
+    pub fn new(items: Vec<DataSetItem>, batch_size: usize, drop_last: bool) -> DataLoader {
+        if batch_size == 0 {
+            panic!("Batch size must be positive");
+        }
+        DataLoader {
+            positions: (0..items.len()).collect(),
+            items,
+            batch_size,
+            drop_last,
+        }
+    }
+
+    pub fn items(&self) -> &[DataSetItem] {
+        &self.items
+    }
+
+    pub fn batch_count(&self) -> usize {
+        if self.drop_last {
+            self.items.len() / self.batch_size
+        } else {
+            self.items.len().div_ceil(self.batch_size)
+        }
+    }
+
+    pub fn shuffle(&mut self, rng: &mut impl Rng) {
+        let mut permutation: Vec<usize> = (0..self.items.len()).collect();
+        permutation.shuffle(rng);
+        let positions: Vec<usize> = permutation.iter().map(|&i| self.positions[i]).collect();
+        self.set_order(&positions);
+    }
+
+    /// The current order as indices into the original dataset.
+    pub fn order(&self) -> &[usize] {
+        &self.positions
+    }
+
+    /// Rearranges the items into `order`, as previously returned by `order`.
+    pub fn set_order(&mut self, order: &[usize]) {
+        if order.len() != self.items.len() {
+            panic!(
+                "Order has {} entries but the loader has {} items",
+                order.len(),
+                self.items.len()
+            );
+        }
+        let mut by_position: Vec<Option<DataSetItem>> = vec![None; self.items.len()];
+        for (item, &position) in std::mem::take(&mut self.items)
+            .into_iter()
+            .zip(&self.positions)
+        {
+            by_position[position] = Some(item);
+        }
+        self.items = order
+            .iter()
+            .map(|&position| {
+                by_position[position]
+                    .take()
+                    .unwrap_or_else(|| panic!("Order is not a permutation at {}", position))
+            })
+            .collect();
+        self.positions = order.to_vec();
+    }
+
+    pub fn batches(&self) -> impl Iterator<Item = &[DataSetItem]> {
+        let limit = if self.drop_last {
+            self.batch_count() * self.batch_size
+        } else {
+            self.items.len()
+        };
+        self.items[..limit].chunks(self.batch_size)
+    }
+
+    /// Shuffles and returns the batches of the next epoch.
+    pub fn epoch(&mut self, rng: &mut impl Rng) -> impl Iterator<Item = &[DataSetItem]> {
+        self.shuffle(rng);
+        self.batches()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::SeededRng;
+
+    fn dataset(len: usize) -> Vec<DataSetItem> {
+        (0..len)
+            .map(|i| DataSetItem {
+                input: vec![i as f64],
+                output: vec![0.0],
+            })
+            .collect()
+    }
+
+    fn ids(batch: &[DataSetItem]) -> Vec<usize> {
+        batch.iter().map(|item| item.input[0] as usize).collect()
+    }
+
+    #[test]
+    fn epoch_visits_every_item_once() {
+        let mut loader = DataLoader::new(dataset(10), 3, false);
+        let mut rng = SeededRng::from_seed(0);
+
+        let batches: Vec<Vec<usize>> = loader.epoch(&mut rng).map(ids).collect();
+        assert_eq!(batches.len(), 4);
+        assert_eq!(loader.batch_count(), 4);
+        assert_eq!(batches[3].len(), 1);
+
+        let mut seen: Vec<usize> = batches.concat();
+        seen.sort();
+        assert_eq!(seen, (0..10).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn drop_last_skips_partial_batch() {
+        let mut loader = DataLoader::new(dataset(10), 3, true);
+        let batches: Vec<Vec<usize>> = loader
+            .epoch(&mut SeededRng::from_seed(0))
+            .map(ids)
+            .collect();
+        assert_eq!(batches.len(), 3);
+        assert_eq!(loader.batch_count(), 3);
+        assert!(batches.iter().all(|b| b.len() == 3));
+    }
+
+    #[test]
+    fn order_can_be_restored() {
+        let mut loader = DataLoader::new(dataset(12), 4, false);
+        let mut rng = SeededRng::from_seed(3);
+        loader.shuffle(&mut rng);
+        assert_eq!(ids(loader.items()), loader.order());
+
+        let mut restored = DataLoader::new(dataset(12), 4, false);
+        restored.set_order(loader.order());
+        let mut rng_copy = rng.clone();
+        let expected: Vec<Vec<usize>> = loader.epoch(&mut rng).map(ids).collect();
+        let actual: Vec<Vec<usize>> = restored.epoch(&mut rng_copy).map(ids).collect();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "not a permutation")]
+    fn set_order_rejects_duplicates() {
+        let mut loader = DataLoader::new(dataset(3), 1, false);
+        loader.set_order(&[0, 0, 1]);
+    }
+
+    #[test]
+    fn order_changes_between_epochs() {
+        let mut loader = DataLoader::new(dataset(20), 20, false);
+        let mut rng = SeededRng::from_seed(1);
+        let first: Vec<Vec<usize>> = loader.epoch(&mut rng).map(ids).collect();
+        let second: Vec<Vec<usize>> = loader.epoch(&mut rng).map(ids).collect();
+        assert_ne!(first, second);
+    }
+}
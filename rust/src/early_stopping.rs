@@ -0,0 +1,295 @@
+use crate::matrix::Matrix;
+use crate::network::Network;
+use crate::optimizer::Optimizer;
+use crate::validation::Evaluation;
+
+/// The validation metric a controller watches. Loss is minimized, accuracy
+/// maximized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MonitorKey {
+    Loss,
+    Accuracy,
+}
+
+impl MonitorKey {
+    fn value(self, evaluation: &Evaluation) -> f64 {
+        match self {
+            MonitorKey::Loss => evaluation.loss,
+            MonitorKey::Accuracy => evaluation.accuracy,
+        }
+    }
+
+    /// Whether `value` beats `best` by more than `min_delta`.
+    fn improved(self, value: f64, best: Option<f64>, min_delta: f64) -> bool {
+        match best {
+            None => !value.is_nan(),
+            Some(best) => match self {
+                MonitorKey::Loss => value < best - min_delta,
+                MonitorKey::Accuracy => value > best + min_delta,
+            },
+        }
+    }
+}
+
+/// Progress of an `EarlyStopping`, e.g. for checkpoints. `best_weights` is
+/// empty unless the best weights are kept.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EarlyStoppingState {
+    pub best: Option<f64>,
+    pub wait: u32,
+    pub best_weights: Vec<Matrix>,
+}
+
+/// Counters of a `ReduceLrOnPlateau`, e.g. for checkpoints.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlateauState {
+    pub best: Option<f64>,
+    pub wait: u32,
+    pub cooldown_counter: u32,
+}
+
+/// Stops training once the monitored metric has not improved by more than
+/// `min_delta` for `patience` consecutive evaluations.
+pub struct EarlyStopping {
+    pub monitor: MonitorKey,
+    pub patience: u32,
+    pub min_delta: f64,
+    pub restore_best_weights: bool,
+    best: Option<f64>,
+    best_weights: Vec<Matrix>,
+    wait: u32,
+}
+
+impl EarlyStopping {
+    pub fn new(
+        monitor: MonitorKey,
+        patience: u32,
+        min_delta: f64,
+        restore_best_weights: bool,
+    ) -> EarlyStopping {
+        EarlyStopping {
+            monitor,
+            patience,
+            min_delta,
+            restore_best_weights,
+            best: None,
+            best_weights: Vec::new(),
+            wait: 0,
+        }
+    }
+
+    /// Records an evaluation of `network` and returns true when training
+    /// should stop. When stopping with `restore_best_weights` the weights of
+    /// the best evaluation are put back into the network.
+    pub fn update(&mut self, evaluation: &Evaluation, network: &mut Network) -> bool {
+        let value = self.monitor.value(evaluation);
+        if self.monitor.improved(value, self.best, self.min_delta) {
+            self.best = Some(value);
+            self.wait = 0;
+            if self.restore_best_weights {
+                self.best_weights = network
+                    .layers
+                    .iter()
+                    .map(|l| l.parameters().clone())
+                    .collect();
+            }
+            return false;
+        }
+
+        self.wait += 1;
+        if self.wait < self.patience {
+            return false;
+        }
+        if self.restore_best_weights {
+            self.restore(network);
+        }
+        true
+    }
+
+    /// Best value of the monitored metric seen so far.
+    pub fn best(&self) -> Option<f64> {
+        self.best
+    }
+
+    pub fn state(&self) -> EarlyStoppingState {
+        EarlyStoppingState {
+            best: self.best,
+            wait: self.wait,
+            best_weights: self.best_weights.clone(),
+        }
+    }
+
+    pub fn set_state(&mut self, state: EarlyStoppingState) {
+        self.best = state.best;
+        self.wait = state.wait;
+        self.best_weights = state.best_weights;
+    }
+
+    /// Puts the weights of the best evaluation back into the network. Does
+    /// nothing unless `restore_best_weights` is set and a best exists.
+    pub fn restore(&self, network: &mut Network) {
+        for (layer, weights) in network.layers.iter_mut().zip(&self.best_weights) {
+            layer.set_parameters(weights.clone());
+        }
+    }
+}
+
+/// Multiplies the optimizer's learning rate scale by `factor` once the
+/// monitored metric has not improved for `patience` evaluations, then waits
+/// `cooldown` evaluations before counting again.
+pub struct ReduceLrOnPlateau {
+    pub monitor: MonitorKey,
+    pub factor: f64,
+    pub patience: u32,
+    pub min_delta: f64,
+    pub cooldown: u32,
+    /// Lower bound for the accumulated scale.
+    pub min_scale: f64,
+    best: Option<f64>,
+    wait: u32,
+    cooldown_counter: u32,
+}
+
+impl ReduceLrOnPlateau {
+    pub fn new(monitor: MonitorKey, factor: f64, patience: u32) -> ReduceLrOnPlateau {
+        if factor <= 0.0 || factor >= 1.0 {
+            panic!("Reduction factor must be in (0, 1), got {}", factor);
+        }
+        ReduceLrOnPlateau {
+            monitor,
+            factor,
+            patience,
+            min_delta: 0.0,
+            cooldown: 0,
+            min_scale: 0.0,
+            best: None,
+            wait: 0,
+            cooldown_counter: 0,
+        }
+    }
+
+    /// Records an evaluation and returns true when the learning rate was
+    /// reduced.
+    pub fn update(&mut self, evaluation: &Evaluation, optimizer: &mut dyn Optimizer) -> bool {
+        let value = self.monitor.value(evaluation);
+        if self.cooldown_counter > 0 {
+            self.cooldown_counter -= 1;
+            self.wait = 0;
+        }
+        if self.monitor.improved(value, self.best, self.min_delta) {
+            self.best = Some(value);
+            self.wait = 0;
+            return false;
+        }
+        if self.cooldown_counter > 0 {
+            return false;
+        }
+
+        self.wait += 1;
+        if self.wait < self.patience {
+            return false;
+        }
+        self.wait = 0;
+        self.cooldown_counter = self.cooldown;
+
+        let scale = optimizer.learning_rate_scale();
+        let reduced = (scale * self.factor).max(self.min_scale);
+        optimizer.set_learning_rate_scale(reduced);
+        reduced < scale
+    }
+
+    pub fn state(&self) -> PlateauState {
+        PlateauState {
+            best: self.best,
+            wait: self.wait,
+            cooldown_counter: self.cooldown_counter,
+        }
+    }
+
+    pub fn set_state(&mut self, state: PlateauState) {
+        self.best = state.best;
+        self.wait = state.wait;
+        self.cooldown_counter = state.cooldown_counter;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activation::ActivationFunctionKey;
+    use crate::error::ErrorFunctionKey;
+    use crate::network::create_network;
+    use crate::optimizer::sgd_optimizer;
+    use crate::schedule::Constant;
+
+    fn loss(loss: f64) -> Evaluation {
+        Evaluation {
+            loss,
+            accuracy: 0.0,
+        }
+    }
+
+    fn network() -> Network {
+        let mut network = create_network(ErrorFunctionKey::MeanSquared);
+        network.seed(0);
+        network.add(ActivationFunctionKey::Sigmoid, 1);
+        network.initialize(1, None);
+        network
+    }
+
+    #[test]
+    fn stops_after_patience() {
+        let mut network = network();
+        let mut stopping = EarlyStopping::new(MonitorKey::Loss, 2, 0.1, false);
+        assert!(!stopping.update(&loss(1.0), &mut network));
+        assert!(!stopping.update(&loss(0.5), &mut network));
+        // Improvements smaller than min_delta do not reset the patience.
+        assert!(!stopping.update(&loss(0.45), &mut network));
+        assert!(stopping.update(&loss(0.6), &mut network));
+        assert_eq!(stopping.best(), Some(0.5));
+    }
+
+    #[test]
+    fn monitors_accuracy_upwards() {
+        let mut network = network();
+        let mut stopping = EarlyStopping::new(MonitorKey::Accuracy, 1, 0.0, false);
+        let accuracy = |accuracy| Evaluation {
+            loss: 0.0,
+            accuracy,
+        };
+        assert!(!stopping.update(&accuracy(0.5), &mut network));
+        assert!(!stopping.update(&accuracy(0.7), &mut network));
+        assert!(stopping.update(&accuracy(0.6), &mut network));
+    }
+
+    #[test]
+    fn restores_best_weights() {
+        let mut network = network();
+        let best = network.layers[0].parameters().items.clone();
+        let mut stopping = EarlyStopping::new(MonitorKey::Loss, 1, 0.0, true);
+        assert!(!stopping.update(&loss(0.1), &mut network));
+
+        let worse = network.layers[0].parameters().scale(3.0);
+        network.layers[0].set_parameters(worse);
+        assert!(stopping.update(&loss(0.2), &mut network));
+        assert_eq!(network.layers[0].parameters().items, best);
+    }
+
+    #[test]
+    fn reduces_learning_rate_with_cooldown() {
+        let mut optimizer = sgd_optimizer(Constant(1.0));
+        let mut plateau = ReduceLrOnPlateau::new(MonitorKey::Loss, 0.5, 2);
+        plateau.cooldown = 1;
+        plateau.min_scale = 0.2;
+
+        let reduced: Vec<bool> = [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.5, 1.0, 1.0, 1.0, 1.0, 1.0]
+            .iter()
+            .map(|&l| plateau.update(&loss(l), &mut optimizer))
+            .collect();
+        assert_eq!(
+            reduced,
+            vec![false, false, true, false, true, false, false, false, true, false, false, false]
+        );
+        assert_eq!(optimizer.learning_rate_scale(), 0.2);
+    }
+}
@@ -1,6 +1,7 @@
+use crate::activation::ActivationFunctionKey;
 use crate::matrix::Matrix;
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ErrorFunctionKey {
     CrossEntropy,
     MeanSquared,
@@ -50,7 +51,90 @@ pub const MSE_LOSS: ErrorFN = ErrorFN {
             let diff = output.get(i, 0) - expected.get(i, 0);
             sum += diff.powi(2);
         }
-        sum
+        0.5 * sum
     },
     grad: |output, expected| -> Matrix { output.map(&|x, i, j| -> f64 { x - expected.get(i, j) }) },
 };
+
+pub fn error_function(key: ErrorFunctionKey) -> ErrorFN {
+    match key {
+        ErrorFunctionKey::CrossEntropy => CE_LOSS,
+        ErrorFunctionKey::LogLoss => LOG_LOSS,
+        ErrorFunctionKey::MeanSquared => MSE_LOSS,
+    }
+}
+
+/// Gradient of the loss with respect to the weighted sums of an output
+/// layer with `activation`, for pairs where it has a simpler closed form
+/// than chaining the two gradients. Softmax with cross-entropy gives
+/// `output - expected`, which stays finite when an output underflows to 0.
+pub fn output_delta(
+    loss: ErrorFunctionKey,
+    activation: ActivationFunctionKey,
+) -> Option<fn(output: &Matrix, expected: &Matrix) -> Matrix> {
+    match (loss, activation) {
+        (ErrorFunctionKey::CrossEntropy, ActivationFunctionKey::Softmax) => {
+            Some(|output, expected| output.map(&|a, i, j| a - expected.get(i, j)))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const H: f64 = 1e-6;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    fn assert_gradient(error: ErrorFN, output: Vec<f64>, expected: Vec<f64>) {
+        let expected = Matrix::from(&expected);
+        let grad = (error.grad)(&Matrix::from(&output), &expected);
+        for i in 0..output.len() {
+            let mut plus = output.clone();
+            plus[i] += H;
+            let mut minus = output.clone();
+            minus[i] -= H;
+            let numeric = ((error.loss)(&Matrix::from(&plus), &expected)
+                - (error.loss)(&Matrix::from(&minus), &expected))
+                / (2.0 * H);
+            assert_close(grad.get(i, 0), numeric);
+        }
+    }
+
+    #[test]
+    fn log_loss() {
+        let output = Matrix::from(&vec![0.8]);
+        assert_close(
+            (LOG_LOSS.loss)(&output, &Matrix::from(&vec![1.0])),
+            -(0.8_f64.ln()),
+        );
+        assert_close(
+            (LOG_LOSS.loss)(&output, &Matrix::from(&vec![0.0])),
+            -(0.2_f64.ln()),
+        );
+        assert_gradient(LOG_LOSS, vec![0.3], vec![1.0]);
+        assert_gradient(LOG_LOSS, vec![0.6], vec![0.0]);
+    }
+
+    #[test]
+    fn cross_entropy() {
+        let output = Matrix::from(&vec![0.1, 0.7, 0.2]);
+        let expected = Matrix::from(&vec![0.0, 1.0, 0.0]);
+        assert_close((CE_LOSS.loss)(&output, &expected), -(0.7_f64.ln()));
+        assert_gradient(CE_LOSS, vec![0.1, 0.7, 0.2], vec![0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn mean_squared() {
+        let output = Matrix::from(&vec![0.5, -1.0]);
+        let expected = Matrix::from(&vec![1.0, 1.0]);
+        assert_close((MSE_LOSS.loss)(&output, &expected), 0.5 * (0.25 + 4.0));
+        let grad = (MSE_LOSS.grad)(&output, &expected);
+        assert_eq!(grad.items, vec![-0.5, -2.0]);
+        assert_gradient(MSE_LOSS, vec![0.5, -1.0], vec![1.0, 1.0]);
+    }
+}
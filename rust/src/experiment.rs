@@ -0,0 +1,304 @@
+use std::io;
+use std::path::Path;
+
+use crate::data_loader::DataLoader;
+use crate::history::{save_results, HistoryEntry, TrainingHistory};
+use crate::network::Network;
+use crate::optimizer::Optimizer;
+use crate::utils::DataSetItem;
+use crate::validation::{evaluate, Evaluation};
+
+/// A named optimizer factory. A fresh optimizer is created for every run.
+pub struct OptimizerConfig {
+    pub name: String,
+    pub create: Box<dyn Fn() -> Box<dyn Optimizer>>,
+}
+
+impl OptimizerConfig {
+    pub fn new<F>(name: &str, create: F) -> OptimizerConfig
+    where
+        F: Fn() -> Box<dyn Optimizer> + 'static,
+    {
+        OptimizerConfig {
+            name: name.to_string(),
+            create: Box::new(create),
+        }
+    }
+}
+
+/// Trains the same architecture with every optimizer configuration once per
+/// seed, recording train and test scores before training and after each
+/// epoch.
+pub struct Experiment {
+    pub epochs: u64,
+    pub batch_size: usize,
+    pub seeds: Vec<u64>,
+}
+
+pub struct ExperimentResult {
+    /// One history per optimizer, averaged over the seeds.
+    pub averaged: Vec<TrainingHistory>,
+    /// `runs[s]` holds the histories of every optimizer for `seeds[s]`.
+    pub runs: Vec<Vec<TrainingHistory>>,
+}
+
+impl Experiment {
+    /// `build` must return an uninitialized network with its layers added.
+    /// It is seeded before each run and initialized for inputs as wide as
+    /// those of the training data.
+    pub fn run<B>(
+        &self,
+        build: B,
+        optimizers: &[OptimizerConfig],
+        train_data: &[DataSetItem],
+        test_data: &[DataSetItem],
+    ) -> ExperimentResult
+    where
+        B: Fn() -> Network,
+    {
+        if train_data.is_empty() {
+            panic!("Can not run an experiment without training data");
+        }
+        if self.batch_size > train_data.len() {
+            panic!(
+                "Batch size {} is larger than the dataset ({})",
+                self.batch_size,
+                train_data.len()
+            );
+        }
+
+        let mut runs = Vec::with_capacity(self.seeds.len());
+        for &seed in &self.seeds {
+            let mut histories = Vec::with_capacity(optimizers.len());
+            for config in optimizers {
+                let mut network = build();
+                network.seed(seed);
+                histories.push(self.train(&mut network, config, train_data, test_data));
+            }
+            runs.push(histories);
+        }
+
+        ExperimentResult {
+            averaged: average_histories(&runs.concat()),
+            runs,
+        }
+    }
+
+    fn train(
+        &self,
+        network: &mut Network,
+        config: &OptimizerConfig,
+        train_data: &[DataSetItem],
+        test_data: &[DataSetItem],
+    ) -> TrainingHistory {
+        let mut loader = DataLoader::new(train_data.to_vec(), self.batch_size, true);
+        loader.shuffle(&mut network.rng);
+        network.initialize(train_data[0].input.len(), None);
+
+        let mut optimizer = (config.create)();
+        let mut history = TrainingHistory::new(&config.name);
+        let mut i = 0;
+        history.record(
+            i,
+            evaluate(network, loader.items()),
+            evaluate(network, test_data),
+        );
+        for _ in 0..self.epochs {
+            for batch in loader.epoch(&mut network.rng) {
+                let data = network.compute_gradients(batch);
+                optimizer.do_update(&data, network, i);
+                i += 1;
+            }
+            history.record(
+                i,
+                evaluate(network, loader.items()),
+                evaluate(network, test_data),
+            );
+        }
+        history
+    }
+}
+
+impl ExperimentResult {
+    /// Writes `results-<n>.json` for every seed (numbered from 1) and the
+    /// averaged `results.json` into `directory`, matching the files used by
+    /// `process-results.ts` and `plots/`.
+    pub fn save<P: AsRef<Path>>(&self, directory: P) -> io::Result<()> {
+        let directory = directory.as_ref();
+        for (n, run) in self.runs.iter().enumerate() {
+            save_results(directory.join(format!("results-{}.json", n + 1)), run)?;
+        }
+        save_results(directory.join("results.json"), &self.averaged)
+    }
+}
+
+/// Groups the histories by optimizer name, in order of first appearance, and
+/// averages their scores entry by entry. Runs of different length are
+/// truncated to the shortest one.
+pub fn average_histories(histories: &[TrainingHistory]) -> Vec<TrainingHistory> {
+    let mut groups: Vec<Vec<&TrainingHistory>> = Vec::new();
+    for history in histories {
+        match groups
+            .iter_mut()
+            .find(|group| group[0].optimizer == history.optimizer)
+        {
+            Some(group) => group.push(history),
+            None => groups.push(vec![history]),
+        }
+    }
+
+    let mut averages = Vec::with_capacity(groups.len());
+    for group in groups {
+        let n = group.len() as f64;
+        let length = group.iter().map(|h| h.results.len()).min().unwrap_or(0);
+
+        let mut average = TrainingHistory::new(&group[0].optimizer);
+        average.took = group.iter().map(|h| h.took).sum::<f64>() / n;
+        for i in 0..length {
+            let mean = |score: fn(&HistoryEntry) -> Evaluation| Evaluation {
+                loss: group.iter().map(|h| score(&h.results[i]).loss).sum::<f64>() / n,
+                accuracy: group
+                    .iter()
+                    .map(|h| score(&h.results[i]).accuracy)
+                    .sum::<f64>()
+                    / n,
+            };
+            average.results.push(HistoryEntry {
+                iteration: group[0].results[i].iteration,
+                train: mean(|entry| entry.train),
+                test: mean(|entry| entry.test),
+            });
+        }
+        averages.push(average);
+    }
+    averages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activation::ActivationFunctionKey;
+    use crate::error::ErrorFunctionKey;
+    use crate::network::create_network;
+    use crate::optimizer::sgd_optimizer;
+    use crate::schedule::Constant;
+
+    fn history(name: &str, took: f64, losses: &[f64]) -> TrainingHistory {
+        let mut history = TrainingHistory::new(name);
+        for (i, &loss) in losses.iter().enumerate() {
+            let evaluation = Evaluation {
+                loss,
+                accuracy: loss / 8.0,
+            };
+            history.record(i as u64 * 10, evaluation, evaluation);
+        }
+        history.took = took;
+        history
+    }
+
+    #[test]
+    fn averages_by_optimizer() {
+        let averaged = average_histories(&[
+            history("SGD", 1.0, &[4.0, 2.0]),
+            history("Momentum", 2.0, &[1.0, 1.0]),
+            history("SGD", 3.0, &[2.0, 1.0, 0.5]),
+        ]);
+        assert_eq!(averaged.len(), 2);
+        assert_eq!(averaged[0].optimizer, "SGD");
+        assert_eq!(averaged[0].took, 2.0);
+        let losses: Vec<f64> = averaged[0].results.iter().map(|e| e.test.loss).collect();
+        assert_eq!(losses, vec![3.0, 1.5]);
+        assert_eq!(averaged[0].results[1].iteration, 10);
+        assert_eq!(averaged[0].results[1].train.accuracy, 0.1875);
+        assert_eq!(averaged[1].results.len(), 2);
+    }
+
+    fn xor_dataset() -> Vec<DataSetItem> {
+        [
+            ([1.0, 1.0], 0.0),
+            ([1.0, 0.0], 1.0),
+            ([0.0, 1.0], 1.0),
+            ([0.0, 0.0], 0.0),
+        ]
+        .iter()
+        .map(|(input, output)| DataSetItem {
+            input: input.to_vec(),
+            output: vec![*output],
+        })
+        .collect()
+    }
+
+    fn run() -> ExperimentResult {
+        let build = || {
+            let mut network = create_network(ErrorFunctionKey::LogLoss);
+            network.add(ActivationFunctionKey::TanH, 3);
+            network.add(ActivationFunctionKey::Sigmoid, 1);
+            network
+        };
+        let optimizers = [
+            OptimizerConfig::new("SGD", || Box::new(sgd_optimizer(Constant(0.5)))),
+            OptimizerConfig::new("Momentum", || {
+                let mut optimizer = sgd_optimizer(Constant(0.5));
+                optimizer.momentum = 0.5;
+                Box::new(optimizer)
+            }),
+        ];
+        let experiment = Experiment {
+            epochs: 5,
+            batch_size: 2,
+            seeds: vec![1, 2, 3],
+        };
+        experiment.run(build, &optimizers, &xor_dataset(), &xor_dataset())
+    }
+
+    #[test]
+    fn runs_every_optimizer_for_every_seed() {
+        let result = run();
+        assert_eq!(result.runs.len(), 3);
+        for run in &result.runs {
+            let names: Vec<&str> = run.iter().map(|h| h.optimizer.as_str()).collect();
+            assert_eq!(names, vec!["SGD", "Momentum"]);
+            let iterations: Vec<u64> = run[0].results.iter().map(|e| e.iteration).collect();
+            assert_eq!(iterations, vec![0, 2, 4, 6, 8, 10]);
+        }
+        assert_ne!(result.runs[0][0].results, result.runs[1][0].results);
+
+        let mean = result
+            .runs
+            .iter()
+            .map(|run| run[1].results[5].test.loss)
+            .sum::<f64>()
+            / 3.0;
+        assert!((result.averaged[1].results[5].test.loss - mean).abs() < 1e-12);
+    }
+
+    #[test]
+    fn seeded_experiments_are_reproducible() {
+        let a = run();
+        let b = run();
+        for (run_a, run_b) in a.runs.iter().zip(&b.runs) {
+            for (history_a, history_b) in run_a.iter().zip(run_b) {
+                assert_eq!(history_a.results, history_b.results);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Can not run an experiment without training data")]
+    fn rejects_empty_training_data() {
+        let experiment = Experiment {
+            epochs: 1,
+            batch_size: 0,
+            seeds: vec![1],
+        };
+        let optimizers = [OptimizerConfig::new("SGD", || {
+            Box::new(sgd_optimizer(Constant(0.5)))
+        })];
+        experiment.run(
+            || create_network(ErrorFunctionKey::LogLoss),
+            &optimizers,
+            &[],
+            &xor_dataset(),
+        );
+    }
+}
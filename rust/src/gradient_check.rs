@@ -0,0 +1,403 @@
+use crate::matrix::Matrix;
+use crate::network::Network;
+use crate::utils::DataSetItem;
+
+const MIN_DENOMINATOR: f64 = 1e-8;
+
+pub struct LayerGradientCheck {
+    pub max_relative_error: f64,
+    pub max_absolute_error: f64,
+}
+
+pub struct GradientCheckResult {
+    pub layers: Vec<LayerGradientCheck>,
+}
+
+impl GradientCheckResult {
+    pub fn max_relative_error(&self) -> f64 {
+        self.layers
+            .iter()
+            .map(|l| l.max_relative_error)
+            .fold(0.0, f64::max)
+    }
+
+    pub fn passed(&self, tolerance: f64) -> bool {
+        self.max_relative_error() <= tolerance
+    }
+
+    pub fn print(&self) {
+        for (i, layer) in self.layers.iter().enumerate() {
+            println!(
+                "Layer {}: max relative error {:e}, max absolute error {:e}",
+                i, layer.max_relative_error, layer.max_absolute_error
+            );
+        }
+    }
+}
+
+/// Compares the gradients from `compute_gradients` against central finite
+/// differences of the loss over `data`, including
+/// weight penalties. Dropout makes the loss random, so the network should
+/// not contain dropout layers. Weights are restored before returning.
+#[allow(clippy::needless_range_loop)]
+pub fn check_gradients(
+    network: &mut Network,
+    data: &[DataSetItem],
+    epsilon: f64,
+) -> GradientCheckResult {
+    let analytic = network.compute_gradients(data).gradients;
+
+    let mut layers = Vec::new();
+    for l in 0..network.layers.len() {
+        let original = network.layers[l].parameters().clone();
+        let mut check = LayerGradientCheck {
+            max_relative_error: 0.0,
+            max_absolute_error: 0.0,
+        };
+
+        for i in 0..original.rows {
+            for j in 0..original.cols {
+                let value = original.get(i, j);
+
+                network.layers[l].set_parameters(perturb(&original, i, j, value + epsilon));
+                let loss_plus = compute_loss(network, data);
+                network.layers[l].set_parameters(perturb(&original, i, j, value - epsilon));
+                let loss_minus = compute_loss(network, data);
+
+                let numeric = (loss_plus - loss_minus) / (2.0 * epsilon);
+                let expected = analytic[l].get(i, j);
+
+                let absolute = (numeric - expected).abs();
+                let relative = absolute / (numeric.abs() + expected.abs()).max(MIN_DENOMINATOR);
+
+                check.max_absolute_error = check.max_absolute_error.max(absolute);
+                check.max_relative_error = check.max_relative_error.max(relative);
+            }
+        }
+        network.layers[l].set_parameters(original);
+        layers.push(check);
+    }
+
+    GradientCheckResult { layers }
+}
+
+fn compute_loss(network: &Network, data: &[DataSetItem]) -> f64 {
+    let mut total_loss = 0.0;
+    for item in data {
+        total_loss += network.evaluate_item(item).loss;
+    }
+    total_loss / data.len() as f64 + network.regularization_loss()
+}
+
+fn perturb(weights: &Matrix, i: usize, j: usize, value: f64) -> Matrix {
+    let mut result = weights.clone();
+    result.set(i, j, value);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activation::ActivationFunctionKey;
+    use crate::conv::{Conv2d, Window};
+    use crate::error::ErrorFunctionKey;
+    use crate::layer::{Dense, ForwardPassResult, Layer, Shape};
+    use crate::network::create_network;
+    use crate::pooling::PoolKey;
+    use crate::random::SeededRng;
+    use crate::regularizer::RegularizerKey;
+
+    const EPSILON: f64 = 1e-5;
+    const TOLERANCE: f64 = 1e-6;
+
+    fn fixed_weights(rows: usize, cols: usize, offset: f64) -> Vec<Vec<f64>> {
+        (0..rows)
+            .map(|i| {
+                (0..cols)
+                    .map(|j| ((i * cols + j) as f64 + offset).sin() * 0.8)
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn dataset(outputs: &[Vec<f64>]) -> Vec<DataSetItem> {
+        let inputs = [
+            vec![0.3, -0.7, 0.9],
+            vec![-0.4, 0.2, 0.6],
+            vec![0.8, 0.5, -0.1],
+        ];
+        inputs
+            .iter()
+            .zip(outputs.iter())
+            .map(|(input, output)| DataSetItem {
+                input: input.clone(),
+                output: output.clone(),
+            })
+            .collect()
+    }
+
+    fn run_check(
+        hidden: ActivationFunctionKey,
+        output: ActivationFunctionKey,
+        error: ErrorFunctionKey,
+        outputs: &[Vec<f64>],
+    ) -> GradientCheckResult {
+        let output_size = outputs[0].len();
+        let mut network = create_network(error);
+        network.add(hidden, 4);
+        network.add(output, output_size as u32);
+        network.initialize(
+            3,
+            Some(vec![
+                fixed_weights(4, 4, 0.5),
+                fixed_weights(output_size, 5, 1.5),
+            ]),
+        );
+        check_gradients(&mut network, &dataset(outputs), EPSILON)
+    }
+
+    fn hidden_activations() -> Vec<ActivationFunctionKey> {
+        vec![
+            ActivationFunctionKey::Sigmoid,
+            ActivationFunctionKey::TanH,
+            ActivationFunctionKey::ReLu,
+        ]
+    }
+
+    #[test]
+    fn sigmoid_output_with_every_loss() {
+        let one_hot = vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![1.0, 0.0]];
+        let binary = vec![vec![1.0], vec![0.0], vec![1.0]];
+        for hidden in hidden_activations() {
+            for (error, outputs) in [
+                (ErrorFunctionKey::MeanSquared, &one_hot),
+                (ErrorFunctionKey::CrossEntropy, &one_hot),
+                (ErrorFunctionKey::LogLoss, &binary),
+            ] {
+                let result = run_check(hidden, ActivationFunctionKey::Sigmoid, error, outputs);
+                assert!(result.passed(TOLERANCE), "{}", result.max_relative_error());
+            }
+        }
+    }
+
+    #[test]
+    fn tanh_and_relu_output_with_mean_squared() {
+        let targets = vec![vec![0.5, -0.2], vec![0.1, 0.7], vec![-0.3, 0.4]];
+        for hidden in hidden_activations() {
+            for output in [ActivationFunctionKey::TanH, ActivationFunctionKey::ReLu] {
+                let result = run_check(hidden, output, ErrorFunctionKey::MeanSquared, &targets);
+                assert!(result.passed(TOLERANCE), "{}", result.max_relative_error());
+            }
+        }
+    }
+
+    #[test]
+    fn softmax_output_with_cross_entropy() {
+        let one_hot = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        for hidden in hidden_activations() {
+            let result = run_check(
+                hidden,
+                ActivationFunctionKey::Softmax,
+                ErrorFunctionKey::CrossEntropy,
+                &one_hot,
+            );
+            assert!(result.passed(TOLERANCE), "{}", result.max_relative_error());
+        }
+    }
+
+    #[test]
+    fn regularized_layers() {
+        let one_hot = vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![1.0, 0.0]];
+        let mut network = create_network(ErrorFunctionKey::CrossEntropy);
+        let mut hidden = Dense::new(4, ActivationFunctionKey::TanH);
+        hidden.regularizer = Some(RegularizerKey::L2(0.3));
+        network.add_layer(hidden);
+        let mut output = Dense::new(2, ActivationFunctionKey::Softmax);
+        output.regularizer = Some(RegularizerKey::L1L2 { l1: 0.1, l2: 0.2 });
+        network.add_layer(output);
+        network.initialize(
+            3,
+            Some(vec![fixed_weights(4, 4, 0.5), fixed_weights(2, 5, 1.5)]),
+        );
+        let result = check_gradients(&mut network, &dataset(&one_hot), EPSILON);
+        assert!(result.passed(TOLERANCE), "{}", result.max_relative_error());
+    }
+
+    fn window(kernel: usize, stride: usize, padding: usize) -> Window {
+        Window {
+            kernel,
+            stride,
+            padding,
+        }
+    }
+
+    /// An L2 regularized convolution with 3 filters and 3x3 kernels.
+    fn regularized_conv(activation: ActivationFunctionKey) -> Conv2d {
+        let mut layer = Conv2d::new(3, window(3, 1, 1), activation);
+        layer.regularizer = Some(RegularizerKey::L2(0.1));
+        layer
+    }
+
+    /// Checks a network on three 2x4x4 images.
+    fn run_image_check(mut network: Network, weights: Vec<Vec<Vec<f64>>>) -> GradientCheckResult {
+        network.initialize_with_shape(
+            Shape {
+                channels: 2,
+                height: 4,
+                width: 4,
+            },
+            Some(weights),
+        );
+        let data: Vec<DataSetItem> = (0..3)
+            .map(|i| DataSetItem {
+                input: (0..32)
+                    .map(|j| ((i * 32 + j) as f64 * 0.37).sin())
+                    .collect(),
+                output: if i % 2 == 0 {
+                    vec![1.0, 0.0]
+                } else {
+                    vec![0.0, 1.0]
+                },
+            })
+            .collect();
+        check_gradients(&mut network, &data, EPSILON)
+    }
+
+    #[test]
+    fn convolution_layers() {
+        let mut network = create_network(ErrorFunctionKey::CrossEntropy);
+        network.add_layer(regularized_conv(ActivationFunctionKey::TanH));
+        network.add_conv2d(ActivationFunctionKey::Sigmoid, 2, window(2, 2, 0));
+        network.add_flatten();
+        network.add(ActivationFunctionKey::Softmax, 2);
+        let weights = vec![
+            fixed_weights(3, 19, 0.5),
+            fixed_weights(2, 13, 1.5),
+            vec![],
+            fixed_weights(2, 9, 2.5),
+        ];
+        let result = run_image_check(network, weights);
+        assert!(result.passed(TOLERANCE), "{}", result.max_relative_error());
+    }
+
+    #[test]
+    fn pooling_layers() {
+        let mut max = create_network(ErrorFunctionKey::CrossEntropy);
+        max.add_layer(regularized_conv(ActivationFunctionKey::TanH));
+        max.add_pool2d(PoolKey::Max, window(2, 2, 0));
+        max.add_flatten();
+        max.add(ActivationFunctionKey::Softmax, 2);
+        let weights = vec![
+            fixed_weights(3, 19, 0.5),
+            vec![],
+            vec![],
+            fixed_weights(2, 13, 2.5),
+        ];
+        let result = run_image_check(max, weights);
+        assert!(result.passed(TOLERANCE), "{}", result.max_relative_error());
+
+        let mut average = create_network(ErrorFunctionKey::CrossEntropy);
+        average.add_layer(regularized_conv(ActivationFunctionKey::ReLu));
+        average.add_pool2d(PoolKey::Average, window(2, 1, 0));
+        average.add_global_avg_pool();
+        average.add(ActivationFunctionKey::Softmax, 2);
+        let weights = vec![
+            fixed_weights(3, 19, 0.5),
+            vec![],
+            vec![],
+            fixed_weights(2, 4, 2.5),
+        ];
+        let result = run_image_check(average, weights);
+        assert!(result.passed(TOLERANCE), "{}", result.max_relative_error());
+    }
+
+    #[test]
+    fn restores_weights() {
+        let mut network = create_network(ErrorFunctionKey::MeanSquared);
+        network.add(ActivationFunctionKey::Sigmoid, 1);
+        network.initialize(3, Some(vec![fixed_weights(1, 4, 0.0)]));
+        let before = network.layers[0].parameters().items.clone();
+        check_gradients(
+            &mut network,
+            &dataset(&[vec![1.0], vec![0.0], vec![1.0]]),
+            EPSILON,
+        );
+        assert_eq!(before, network.layers[0].parameters().items);
+    }
+
+    /// A user-defined layer: multiplies each value by its own learned
+    /// scale.
+    struct Scale {
+        scales: Matrix,
+    }
+
+    impl Layer for Scale {
+        fn name(&self) -> String {
+            "Scale".to_string()
+        }
+
+        fn output_shape(&self, input: Shape) -> Result<Shape, String> {
+            Ok(input)
+        }
+
+        fn forward(&self, input: &Matrix, _rng: Option<&mut SeededRng>) -> ForwardPassResult {
+            ForwardPassResult {
+                activated: input.hadamard(&self.scales),
+                cache: Matrix::zeros(0, 0),
+            }
+        }
+
+        fn backward(
+            &self,
+            _input: &Matrix,
+            _result: &ForwardPassResult,
+            output_gradient: &Matrix,
+        ) -> Matrix {
+            output_gradient.hadamard(&self.scales)
+        }
+
+        fn parameters(&self) -> &Matrix {
+            &self.scales
+        }
+
+        fn set_parameters(&mut self, parameters: Matrix) {
+            self.scales = parameters;
+        }
+
+        fn gradients(
+            &self,
+            input: &Matrix,
+            _result: &ForwardPassResult,
+            output_gradient: &Matrix,
+            total: &mut Matrix,
+        ) {
+            *total = total.sum(&output_gradient.hadamard(input));
+        }
+    }
+
+    #[test]
+    fn user_defined_layers() {
+        let one_hot = vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![1.0, 0.0]];
+        let mut network = create_network(ErrorFunctionKey::CrossEntropy);
+        network.add(ActivationFunctionKey::TanH, 4);
+        network.add_layer(Scale {
+            scales: Matrix::from(&vec![0.5, -1.5, 2.0, 0.8]),
+        });
+        network.add(ActivationFunctionKey::Softmax, 2);
+        network.initialize(
+            3,
+            Some(vec![
+                fixed_weights(4, 4, 0.5),
+                vec![vec![0.5], vec![-1.5], vec![2.0], vec![0.8]],
+                fixed_weights(2, 5, 1.5),
+            ]),
+        );
+        let result = check_gradients(&mut network, &dataset(&one_hot), EPSILON);
+        assert_eq!(result.layers.len(), 3);
+        assert!(result.passed(TOLERANCE), "{}", result.max_relative_error());
+    }
+}
@@ -0,0 +1,193 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::validation::Evaluation;
+
+/// Scores of one evaluation point, matching the `results` entries of the
+/// plots app.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistoryEntry {
+    pub iteration: u64,
+    pub train: Evaluation,
+    pub test: Evaluation,
+}
+
+/// Training history of a single optimizer run. Serializes to
+/// `{optimizer, took, results: [{iteration, train, test}]}` where `train` and
+/// `test` are `{loss, percentage}` and `took` is in seconds.
+#[derive(Debug, Clone)]
+pub struct TrainingHistory {
+    pub optimizer: String,
+    pub took: f64,
+    pub results: Vec<HistoryEntry>,
+    started: Instant,
+}
+
+impl TrainingHistory {
+    pub fn new(optimizer: &str) -> TrainingHistory {
+        TrainingHistory {
+            optimizer: optimizer.to_string(),
+            took: 0.0,
+            results: Vec::new(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Continues a history saved after `took` seconds, e.g. in a checkpoint.
+    pub fn resume(optimizer: &str, took: f64, results: Vec<HistoryEntry>) -> TrainingHistory {
+        let now = Instant::now();
+        TrainingHistory {
+            optimizer: optimizer.to_string(),
+            took,
+            results,
+            started: now
+                .checked_sub(Duration::from_secs_f64(took))
+                .unwrap_or(now),
+        }
+    }
+
+    /// Appends an entry and updates `took` to the time since creation.
+    pub fn record(&mut self, iteration: u64, train: Evaluation, test: Evaluation) {
+        self.results.push(HistoryEntry {
+            iteration,
+            train,
+            test,
+        });
+        self.took = self.started.elapsed().as_secs_f64();
+    }
+
+    /// The entry with the lowest test loss.
+    pub fn best(&self) -> Option<&HistoryEntry> {
+        self.results
+            .iter()
+            .min_by(|a, b| a.test.loss.total_cmp(&b.test.loss))
+    }
+}
+
+/// Compares everything but the start time, which is not saved.
+impl PartialEq for TrainingHistory {
+    fn eq(&self, other: &TrainingHistory) -> bool {
+        self.optimizer == other.optimizer
+            && self.took == other.took
+            && self.results == other.results
+    }
+}
+
+/// Writes the histories as the JSON array read by `plots/` and
+/// `process-results.ts`.
+pub fn write_results<W: Write>(writer: &mut W, histories: &[TrainingHistory]) -> io::Result<()> {
+    write!(writer, "[")?;
+    for (i, history) in histories.iter().enumerate() {
+        if i > 0 {
+            write!(writer, ",")?;
+        }
+        write!(
+            writer,
+            "{{\"optimizer\":{},\"took\":{},\"results\":[",
+            json_string(&history.optimizer),
+            json_number(history.took)
+        )?;
+        for (j, entry) in history.results.iter().enumerate() {
+            if j > 0 {
+                write!(writer, ",")?;
+            }
+            write!(
+                writer,
+                "{{\"iteration\":{},\"train\":{},\"test\":{}}}",
+                entry.iteration,
+                json_evaluation(&entry.train),
+                json_evaluation(&entry.test)
+            )?;
+        }
+        write!(writer, "]}}")?;
+    }
+    writeln!(writer, "]")
+}
+
+pub fn save_results<P: AsRef<Path>>(path: P, histories: &[TrainingHistory]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_results(&mut writer, histories)?;
+    writer.flush()
+}
+
+fn json_evaluation(evaluation: &Evaluation) -> String {
+    format!(
+        "{{\"loss\":{},\"percentage\":{}}}",
+        json_number(evaluation.loss),
+        json_number(evaluation.accuracy)
+    )
+}
+
+/// JSON has no NaN or infinity, so those are written as `null`.
+fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluation(loss: f64, accuracy: f64) -> Evaluation {
+        Evaluation { loss, accuracy }
+    }
+
+    #[test]
+    fn writes_plots_schema() {
+        let mut history = TrainingHistory::new("Adam");
+        history.record(0, evaluation(2.5, 0.1), evaluation(2.25, 0.125));
+        history.record(300, evaluation(0.5, 0.75), evaluation(f64::NAN, 0.5));
+        history.took = 1.5;
+
+        let mut buffer = Vec::new();
+        write_results(&mut buffer, &[history]).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            concat!(
+                "[{\"optimizer\":\"Adam\",\"took\":1.5,\"results\":[",
+                "{\"iteration\":0,\"train\":{\"loss\":2.5,\"percentage\":0.1},",
+                "\"test\":{\"loss\":2.25,\"percentage\":0.125}},",
+                "{\"iteration\":300,\"train\":{\"loss\":0.5,\"percentage\":0.75},",
+                "\"test\":{\"loss\":null,\"percentage\":0.5}}]}]\n"
+            )
+        );
+    }
+
+    #[test]
+    fn escapes_optimizer_names() {
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\n\"");
+    }
+
+    #[test]
+    fn best_entry_has_lowest_test_loss() {
+        let mut history = TrainingHistory::new("SGD");
+        assert!(history.best().is_none());
+        history.record(0, evaluation(1.0, 0.5), evaluation(1.0, 0.5));
+        history.record(1, evaluation(0.5, 0.5), evaluation(0.25, 0.5));
+        history.record(2, evaluation(0.1, 0.5), evaluation(0.5, 0.5));
+        assert_eq!(history.best().unwrap().iteration, 1);
+    }
+}
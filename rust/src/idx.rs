@@ -0,0 +1,216 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+
+use crate::utils::{one_hot_encode, DataSetItem};
+
+const UNSIGNED_BYTE: u8 = 0x08;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// An unsigned byte tensor stored in the IDX format used by MNIST,
+/// Fashion-MNIST and EMNIST.
+pub struct IdxArray {
+    pub dims: Vec<usize>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum IdxError {
+    Io(io::Error),
+    Gzip(String),
+    Format(String),
+}
+
+impl fmt::Display for IdxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IdxError::Io(e) => write!(f, "{}", e),
+            IdxError::Gzip(message) => write!(f, "invalid gzip data: {}", message),
+            IdxError::Format(message) => write!(f, "invalid idx data: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for IdxError {}
+
+impl From<io::Error> for IdxError {
+    fn from(e: io::Error) -> IdxError {
+        IdxError::Io(e)
+    }
+}
+
+/// Reads an IDX file, decompressing it first if it is gzipped.
+pub fn read_idx<P>(path: P) -> Result<IdxArray, IdxError>
+where
+    P: AsRef<Path>,
+{
+    let bytes = fs::read(path)?;
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let mut decompressed = Vec::new();
+        GzDecoder::new(bytes.as_slice())
+            .read_to_end(&mut decompressed)
+            .map_err(|e| IdxError::Gzip(e.to_string()))?;
+        return parse_idx(&decompressed);
+    }
+    parse_idx(&bytes)
+}
+
+pub fn parse_idx(bytes: &[u8]) -> Result<IdxArray, IdxError> {
+    if bytes.len() < 4 || bytes[0] != 0 || bytes[1] != 0 {
+        return Err(IdxError::Format("bad magic number".to_string()));
+    }
+    if bytes[2] != UNSIGNED_BYTE {
+        return Err(IdxError::Format(format!(
+            "unsupported data type 0x{:02x}",
+            bytes[2]
+        )));
+    }
+
+    let dim_count = bytes[3] as usize;
+    let data_start = 4 + 4 * dim_count;
+    if bytes.len() < data_start {
+        return Err(IdxError::Format("truncated header".to_string()));
+    }
+    let dims: Vec<usize> = bytes[4..data_start]
+        .chunks(4)
+        .map(|d| u32::from_be_bytes([d[0], d[1], d[2], d[3]]) as usize)
+        .collect();
+
+    let found = bytes.len() - data_start;
+    let size = dims
+        .iter()
+        .try_fold(1_usize, |size, &d| size.checked_mul(d));
+    if size != Some(found) {
+        return Err(IdxError::Format(format!(
+            "dimensions {:?} do not match {} bytes of data",
+            dims, found
+        )));
+    }
+
+    Ok(IdxArray {
+        dims,
+        data: bytes[data_start..].to_vec(),
+    })
+}
+
+/// Pairs an image file with a label file. Pixels are scaled to [0, 1] and
+/// labels are one-hot encoded into `classes` outputs.
+pub fn read_idx_dataset<P, Q>(
+    images_path: P,
+    labels_path: Q,
+    classes: usize,
+) -> Result<Vec<DataSetItem>, IdxError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    idx_dataset(&read_idx(images_path)?, &read_idx(labels_path)?, classes)
+}
+
+pub fn idx_dataset(
+    images: &IdxArray,
+    labels: &IdxArray,
+    classes: usize,
+) -> Result<Vec<DataSetItem>, IdxError> {
+    if images.dims.is_empty() || labels.dims.len() != 1 {
+        return Err(IdxError::Format(
+            "expected an image tensor and a label vector".to_string(),
+        ));
+    }
+    let count = images.dims[0];
+    if labels.dims[0] != count {
+        return Err(IdxError::Format(format!(
+            "{} images but {} labels",
+            count, labels.dims[0]
+        )));
+    }
+
+    let sample_size: usize = images.dims[1..].iter().product();
+    let mut result = Vec::with_capacity(count);
+    for (i, &label) in labels.data.iter().enumerate() {
+        if label as usize >= classes {
+            return Err(IdxError::Format(format!(
+                "label {} of item {} is not below {}",
+                label, i, classes
+            )));
+        }
+        let pixels = &images.data[i * sample_size..(i + 1) * sample_size];
+        result.push(DataSetItem {
+            input: pixels.iter().map(|p| *p as f64 / 255.0).collect(),
+            output: one_hot_encode(label as f64, classes),
+        });
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn idx(dims: &[u32], data: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0, 0, UNSIGNED_BYTE, dims.len() as u8];
+        for d in dims {
+            bytes.extend_from_slice(&d.to_be_bytes());
+        }
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn parses_header_and_data() {
+        let array = parse_idx(&idx(&[2, 3], &[1, 2, 3, 4, 5, 6])).unwrap();
+        assert_eq!(array.dims, vec![2, 3]);
+        assert_eq!(array.data, vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        assert!(parse_idx(&[0, 1, 8, 1]).is_err());
+        assert!(parse_idx(&[0, 0, 0x0d, 1, 0, 0, 0, 0]).is_err());
+        assert!(parse_idx(&idx(&[2, 3], &[1, 2, 3])).is_err());
+        // The product of these dimensions overflows a usize.
+        assert!(parse_idx(&idx(&[u32::MAX; 3], &[])).is_err());
+    }
+
+    #[test]
+    fn builds_normalized_dataset() {
+        let images = parse_idx(&idx(&[2, 1, 2], &[0, 255, 51, 102])).unwrap();
+        let labels = parse_idx(&idx(&[2], &[2, 0])).unwrap();
+        let data = idx_dataset(&images, &labels, 3).unwrap();
+        assert_eq!(data.len(), 2);
+        assert_eq!(data[0].input, vec![0.0, 1.0]);
+        assert_eq!(data[0].output, vec![0.0, 0.0, 1.0]);
+        assert_eq!(data[1].input, vec![0.2, 0.4]);
+        assert_eq!(data[1].output, vec![1.0, 0.0, 0.0]);
+
+        assert!(idx_dataset(&images, &labels, 2).is_err());
+        let short = parse_idx(&idx(&[1], &[0])).unwrap();
+        assert!(idx_dataset(&images, &short, 3).is_err());
+    }
+
+    #[test]
+    fn reads_plain_and_gzipped_files() {
+        // Labels [7, 0, 2] gzipped by Python at level 9.
+        let gzipped: [u8; 31] = [
+            31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 99, 96, 224, 96, 100, 96, 96, 96, 102, 103, 96, 2, 0,
+            45, 203, 1, 229, 11, 0, 0, 0,
+        ];
+        let dir = std::env::temp_dir();
+        let plain_path = dir.join(format!("idx-test-{}-labels", std::process::id()));
+        let gzip_path = dir.join(format!("idx-test-{}-labels.gz", std::process::id()));
+        fs::write(&plain_path, idx(&[3], &[7, 0, 2])).unwrap();
+        fs::write(&gzip_path, gzipped).unwrap();
+
+        let plain = read_idx(&plain_path).unwrap();
+        let unzipped = read_idx(&gzip_path).unwrap();
+        fs::remove_file(&plain_path).unwrap();
+        fs::remove_file(&gzip_path).unwrap();
+
+        assert_eq!(plain.data, vec![7, 0, 2]);
+        assert_eq!(unzipped.dims, plain.dims);
+        assert_eq!(unzipped.data, plain.data);
+    }
+}
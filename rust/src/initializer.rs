@@ -0,0 +1,241 @@
+use rand::Rng;
+
+use crate::activation::ActivationFunctionKey;
+use crate::random::sample_normal;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InitializerKey {
+    Uniform(f64),
+    XavierUniform,
+    XavierNormal,
+    HeUniform,
+    HeNormal,
+    LecunUniform,
+    LecunNormal,
+    Orthogonal,
+    Constant(f64),
+}
+
+impl InitializerKey {
+    /// Uniform limits must be positive, or the sampling range is empty.
+    pub fn is_valid(self) -> bool {
+        match self {
+            InitializerKey::Uniform(limit) => limit > 0.0 && limit.is_finite(),
+            _ => true,
+        }
+    }
+}
+
+pub fn default_initializer(activation: ActivationFunctionKey) -> InitializerKey {
+    match activation {
+        ActivationFunctionKey::ReLu => InitializerKey::HeNormal,
+        ActivationFunctionKey::Sigmoid
+        | ActivationFunctionKey::TanH
+        | ActivationFunctionKey::Softmax
+        | ActivationFunctionKey::Linear => InitializerKey::XavierUniform,
+    }
+}
+
+/// Creates a `rows x cols` weight matrix where column 0 holds the biases,
+/// which always start at zero. Fan-in is therefore `cols - 1`.
+pub fn initialize_weights(
+    key: InitializerKey,
+    rows: usize,
+    cols: usize,
+    rng: &mut impl Rng,
+) -> Vec<Vec<f64>> {
+    let fan_in = (cols - 1) as f64;
+    let fan_out = rows as f64;
+
+    let kernel = match key {
+        InitializerKey::Uniform(limit) => uniform(rows, cols - 1, limit, rng),
+        InitializerKey::XavierUniform => {
+            uniform(rows, cols - 1, (6.0 / (fan_in + fan_out)).sqrt(), rng)
+        }
+        InitializerKey::XavierNormal => {
+            normal(rows, cols - 1, (2.0 / (fan_in + fan_out)).sqrt(), rng)
+        }
+        InitializerKey::HeUniform => uniform(rows, cols - 1, (6.0 / fan_in).sqrt(), rng),
+        InitializerKey::HeNormal => normal(rows, cols - 1, (2.0 / fan_in).sqrt(), rng),
+        InitializerKey::LecunUniform => uniform(rows, cols - 1, (3.0 / fan_in).sqrt(), rng),
+        InitializerKey::LecunNormal => normal(rows, cols - 1, (1.0 / fan_in).sqrt(), rng),
+        InitializerKey::Orthogonal => orthogonal(rows, cols - 1, rng),
+        InitializerKey::Constant(value) => vec![vec![value; cols - 1]; rows],
+    };
+
+    kernel
+        .into_iter()
+        .map(|row| {
+            let mut result = Vec::with_capacity(cols);
+            result.push(0.0);
+            result.extend(row);
+            result
+        })
+        .collect()
+}
+
+fn uniform(rows: usize, cols: usize, limit: f64, rng: &mut impl Rng) -> Vec<Vec<f64>> {
+    (0..rows)
+        .map(|_| (0..cols).map(|_| rng.gen_range(-limit..limit)).collect())
+        .collect()
+}
+
+fn normal(rows: usize, cols: usize, std: f64, rng: &mut impl Rng) -> Vec<Vec<f64>> {
+    (0..rows)
+        .map(|_| (0..cols).map(|_| std * sample_normal(rng)).collect())
+        .collect()
+}
+
+fn orthogonal(rows: usize, cols: usize, rng: &mut impl Rng) -> Vec<Vec<f64>> {
+    // Gram-Schmidt over the shorter dimension, so the result has orthonormal
+    // rows when rows <= cols and orthonormal columns otherwise.
+    let transpose = rows > cols;
+    let (short, long) = if transpose {
+        (cols, rows)
+    } else {
+        (rows, cols)
+    };
+
+    let mut basis: Vec<Vec<f64>> = Vec::with_capacity(short);
+    while basis.len() < short {
+        let mut vector: Vec<f64> = (0..long).map(|_| sample_normal(rng)).collect();
+        for b in &basis {
+            let dot: f64 = vector.iter().zip(b).map(|(x, y)| x * y).sum();
+            for k in 0..long {
+                vector[k] -= dot * b[k];
+            }
+        }
+        let norm = vector.iter().map(|x| x * x).sum::<f64>().sqrt();
+        if norm < 1e-10 {
+            continue;
+        }
+        basis.push(vector.iter().map(|x| x / norm).collect());
+    }
+
+    if !transpose {
+        return basis;
+    }
+    (0..rows)
+        .map(|i| (0..cols).map(|j| basis[j][i]).collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::SeededRng;
+
+    fn kernel_stats(weights: &[Vec<f64>]) -> (f64, f64, f64) {
+        let values: Vec<f64> = weights.iter().flat_map(|row| row[1..].to_vec()).collect();
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let variance = values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+        let max = values.iter().fold(0.0_f64, |m, x| m.max(x.abs()));
+        (mean, variance, max)
+    }
+
+    #[test]
+    fn bias_column_is_zero() {
+        let mut rng = SeededRng::from_seed(0);
+        for key in [
+            InitializerKey::Uniform(0.5),
+            InitializerKey::XavierNormal,
+            InitializerKey::HeUniform,
+            InitializerKey::Orthogonal,
+            InitializerKey::Constant(0.3),
+        ] {
+            let weights = initialize_weights(key, 4, 6, &mut rng);
+            assert_eq!(weights.len(), 4);
+            for row in weights {
+                assert_eq!(row.len(), 6);
+                assert_eq!(row[0], 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn variances_follow_fan_in_and_fan_out() {
+        let mut rng = SeededRng::from_seed(1);
+        let (rows, cols) = (200, 301);
+        let fan_in = 300.0;
+        let fan_out = 200.0;
+        let cases = [
+            (InitializerKey::XavierUniform, 2.0 / (fan_in + fan_out)),
+            (InitializerKey::XavierNormal, 2.0 / (fan_in + fan_out)),
+            (InitializerKey::HeUniform, 2.0 / fan_in),
+            (InitializerKey::HeNormal, 2.0 / fan_in),
+            (InitializerKey::LecunUniform, 1.0 / fan_in),
+            (InitializerKey::LecunNormal, 1.0 / fan_in),
+        ];
+        for (key, expected) in cases {
+            let (mean, variance, _) = kernel_stats(&initialize_weights(key, rows, cols, &mut rng));
+            assert!(mean.abs() < 0.01, "{:?} mean {}", key, mean);
+            assert!(
+                (variance - expected).abs() / expected < 0.05,
+                "{:?} variance {} expected {}",
+                key,
+                variance,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn uniform_respects_limit() {
+        let weights = initialize_weights(
+            InitializerKey::Uniform(0.5),
+            10,
+            10,
+            &mut SeededRng::from_seed(2),
+        );
+        let (_, _, max) = kernel_stats(&weights);
+        assert!(max < 0.5);
+    }
+
+    #[test]
+    fn constant_fills_kernel() {
+        let weights = initialize_weights(
+            InitializerKey::Constant(0.25),
+            2,
+            3,
+            &mut SeededRng::from_seed(0),
+        );
+        assert_eq!(weights, vec![vec![0.0, 0.25, 0.25], vec![0.0, 0.25, 0.25]]);
+    }
+
+    #[test]
+    fn orthogonal_is_orthonormal() {
+        let mut rng = SeededRng::from_seed(3);
+        for (rows, cols) in [(3, 6), (5, 4), (4, 5)] {
+            let weights = initialize_weights(InitializerKey::Orthogonal, rows, cols, &mut rng);
+            let kernel: Vec<Vec<f64>> = weights.iter().map(|row| row[1..].to_vec()).collect();
+            let (short, long) = (rows.min(cols - 1), rows.max(cols - 1));
+            let vector = |k: usize, i: usize| {
+                if rows < cols {
+                    kernel[k][i]
+                } else {
+                    kernel[i][k]
+                }
+            };
+            for a in 0..short {
+                for b in 0..short {
+                    let dot: f64 = (0..long).map(|i| vector(a, i) * vector(b, i)).sum();
+                    let expected = if a == b { 1.0 } else { 0.0 };
+                    assert!((dot - expected).abs() < 1e-10);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn defaults_depend_on_activation() {
+        assert_eq!(
+            default_initializer(ActivationFunctionKey::ReLu),
+            InitializerKey::HeNormal
+        );
+        assert_eq!(
+            default_initializer(ActivationFunctionKey::TanH),
+            InitializerKey::XavierUniform
+        );
+    }
+}
@@ -1,104 +1,375 @@
-use crate::activation;
-use crate::error;
+use std::fmt;
+
+use rand::Rng;
+
+use crate::activation::{activation_function, ActivationFN, ActivationFunctionKey};
+use crate::conv::Window;
+use crate::initializer::{default_initializer, initialize_weights, InitializerKey};
 use crate::matrix::Matrix;
+use crate::pooling::PoolKey;
+use crate::random::SeededRng;
+use crate::regularizer::RegularizerKey;
+
+/// Channels x height x width of the values flowing between layers. Values
+/// are stored channel by channel, row by row; flat vectors have a height
+/// and width of 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shape {
+    pub channels: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl Shape {
+    pub fn flat(size: usize) -> Shape {
+        Shape {
+            channels: size,
+            height: 1,
+            width: 1,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.channels * self.height * self.width
+    }
+
+    pub fn is_flat(&self) -> bool {
+        self.height == 1 && self.width == 1
+    }
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_flat() {
+            write!(f, "{}", self.channels)
+        } else {
+            write!(f, "{}x{}x{}", self.channels, self.height, self.width)
+        }
+    }
+}
+
+/// Identifies the built-in layers, so that they can be saved and rebuilt.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LayerKey {
+    Dense,
+    Conv2d(Window),
+    Flatten,
+    Pool2d(PoolKey, Window),
+    GlobalAvgPool,
+    Dropout(f64),
+}
 
 pub struct ForwardPassResult {
-    pub sum: Matrix,
     pub activated: Matrix,
-    pub error: f64,
+    /// Whatever the layer needs again in its backward pass, such as the
+    /// weighted sums before activation.
+    pub cache: Matrix,
 }
 
-pub struct Layer {
-    pub is_output: bool,
-    pub neuron_count: u32,
-    pub weights: Matrix,
-    pub weights_transpose: Matrix,
-    activation_key: activation::ActivationFunctionKey,
-    activation: activation::ActivationFN,
-    error_key: error::ErrorFunctionKey,
-    error: error::ErrorFN,
+static NO_PARAMETERS: Matrix = Matrix {
+    items: Vec::new(),
+    rows: 0,
+    cols: 0,
+};
+
+/// One step of a network. Inputs and outputs are column vectors laid out
+/// as described by `Shape`. Trainable parameters live in a single matrix,
+/// so optimizers, regularizers and checkpoints treat every kind alike;
+/// layers without parameters only need the required methods.
+pub trait Layer {
+    /// Short description shown by `Network::summary`.
+    fn name(&self) -> String;
+
+    /// Output shape for `input`, or why the layer can not be applied to it.
+    fn output_shape(&self, input: Shape) -> Result<Shape, String>;
+
+    /// Prepares the layer for inputs of shape `input`, drawing fresh
+    /// parameters from `rng`.
+    fn initialize(&mut self, _input: Shape, _rng: &mut SeededRng) {}
+
+    /// `rng` is only given while training, for layers such as dropout that
+    /// behave differently then.
+    fn forward(&self, input: &Matrix, rng: Option<&mut SeededRng>) -> ForwardPassResult;
+
+    /// Gradient of the loss with respect to `input`, given the gradient
+    /// with respect to the layer's output. For layers with an `activation`
+    /// it is the gradient with respect to the weighted sums instead: the
+    /// network applies the activation's gradient, so that it can fuse it
+    /// with the loss's.
+    fn backward(
+        &self,
+        input: &Matrix,
+        result: &ForwardPassResult,
+        output_gradient: &Matrix,
+    ) -> Matrix;
+
+    fn parameters(&self) -> &Matrix {
+        &NO_PARAMETERS
+    }
+
+    fn set_parameters(&mut self, parameters: Matrix) {
+        if !parameters.items.is_empty() {
+            panic!("{} has no parameters", self.name());
+        }
+    }
+
+    /// Adds the gradient of the loss with respect to the parameters to
+    /// `total`, given the same `output_gradient` as `backward`.
+    fn gradients(
+        &self,
+        _input: &Matrix,
+        _result: &ForwardPassResult,
+        _output_gradient: &Matrix,
+        _total: &mut Matrix,
+    ) {
+    }
+
+    fn regularizer(&self) -> Option<RegularizerKey> {
+        None
+    }
+
+    fn activation(&self) -> Option<ActivationFunctionKey> {
+        None
+    }
+
+    /// `None` for user-defined layers, which can not be saved.
+    fn key(&self) -> Option<LayerKey> {
+        None
+    }
 }
 
-impl Layer {
-    pub fn forward_pass(&self, input: &Matrix, expected: &Matrix) -> ForwardPassResult {
-        let sum = self.weights.multiply(input);
+/// Fully connected layer. Column 0 of the weights holds the biases.
+pub struct Dense {
+    units: usize,
+    pub initializer: InitializerKey,
+    pub regularizer: Option<RegularizerKey>,
+    weights: Matrix,
+    weights_transpose: Matrix,
+    activation_key: ActivationFunctionKey,
+    activation: ActivationFN,
+}
 
-        let activated = (self.activation.forward)(&sum, self.is_output);
+impl Dense {
+    pub fn new(units: usize, activation: ActivationFunctionKey) -> Dense {
+        Dense {
+            units,
+            initializer: default_initializer(activation),
+            regularizer: None,
+            weights: Matrix::zeros(0, 0),
+            weights_transpose: Matrix::zeros(0, 0),
+            activation_key: activation,
+            activation: activation_function(activation),
+        }
+    }
+}
 
-        let error = if self.is_output {
-            (self.error.loss)(&activated, expected)
-        } else {
-            -1.0
-        };
+impl Layer for Dense {
+    fn name(&self) -> String {
+        "Dense".to_string()
+    }
+
+    fn output_shape(&self, _input: Shape) -> Result<Shape, String> {
+        Ok(Shape::flat(self.units))
+    }
+
+    fn initialize(&mut self, input: Shape, rng: &mut SeededRng) {
+        let weights = initialize_weights(self.initializer, self.units, input.size() + 1, rng);
+        self.set_parameters(Matrix::from(&weights));
+    }
+
+    fn forward(&self, input: &Matrix, _rng: Option<&mut SeededRng>) -> ForwardPassResult {
+        let sum = self.weights.multiply(&input.unshift(1.0));
         ForwardPassResult {
-            sum,
-            activated,
-            error,
+            activated: (self.activation.forward)(&sum),
+            cache: sum,
         }
     }
 
-    pub fn backward_pass(
+    fn backward(
         &self,
-        result: &ForwardPassResult,
-        delta_next: &Matrix,
-        layer_next: &Layer,
+        _input: &Matrix,
+        _result: &ForwardPassResult,
+        output_gradient: &Matrix,
     ) -> Matrix {
-        let weights = &layer_next.weights_transpose;
-        let backward = self.activation.backward;
-        weights
-            .multiply(delta_next)
-            .hadamard(&backward(&result.sum))
-    }
-
-    pub fn output_pass(&self, result: &ForwardPassResult, expected: &Matrix) -> Matrix {
-        if let activation::ActivationFunctionKey::Softmax = self.activation_key {
-            if let error::ErrorFunctionKey::CrossEntropy = self.error_key {
-                return (self.activation.output)(&result.activated, expected);
-            } else {
-                panic!("can only use softmax with cross-entropy");
+        self.weights_transpose.multiply(output_gradient)
+    }
+
+    fn parameters(&self) -> &Matrix {
+        &self.weights
+    }
+
+    fn set_parameters(&mut self, parameters: Matrix) {
+        self.weights = parameters;
+        self.weights_transpose = self.weights.omit(0).transpose();
+    }
+
+    fn gradients(
+        &self,
+        input: &Matrix,
+        _result: &ForwardPassResult,
+        output_gradient: &Matrix,
+        total: &mut Matrix,
+    ) {
+        for i in 0..total.rows {
+            for j in 0..total.cols {
+                let x = if j == 0 { 1.0 } else { input.get(j - 1, 0) };
+                let new_val = total.get(i, j) + output_gradient.get(i, 0) * x;
+                total.set(i, j, new_val);
             }
         }
-        let error = (self.error.grad)(&result.activated, expected);
-        let backward = (self.activation.backward)(&result.sum);
+    }
 
-        error.hadamard(&backward)
+    fn regularizer(&self) -> Option<RegularizerKey> {
+        self.regularizer
     }
 
-    pub fn set_weights(&mut self, weights: Matrix) {
-        self.weights = weights;
-        self.weights_transpose = self.weights.omit(0).transpose();
+    fn activation(&self) -> Option<ActivationFunctionKey> {
+        Some(self.activation_key)
+    }
+
+    fn key(&self) -> Option<LayerKey> {
+        Some(LayerKey::Dense)
+    }
+}
+
+/// Inverted dropout: while training, zeroes each value with probability
+/// `rate` and scales the kept ones by `1 / (1 - rate)`. Passes values
+/// through unchanged otherwise.
+pub struct Dropout {
+    pub rate: f64,
+}
+
+impl Dropout {
+    pub fn new(rate: f64) -> Dropout {
+        Dropout { rate }
+    }
+}
+
+impl Layer for Dropout {
+    fn name(&self) -> String {
+        format!("Dropout {}", self.rate)
+    }
+
+    fn output_shape(&self, input: Shape) -> Result<Shape, String> {
+        Ok(input)
+    }
+
+    fn forward(&self, input: &Matrix, rng: Option<&mut SeededRng>) -> ForwardPassResult {
+        match rng {
+            Some(rng) if self.rate > 0.0 => {
+                let keep = 1.0 / (1.0 - self.rate);
+                let mask: Vec<f64> = (0..input.items.len())
+                    .map(|_| {
+                        if rng.gen::<f64>() < self.rate {
+                            0.0
+                        } else {
+                            keep
+                        }
+                    })
+                    .collect();
+                let mask = Matrix::from(&mask);
+                ForwardPassResult {
+                    activated: input.hadamard(&mask),
+                    cache: mask,
+                }
+            }
+            _ => ForwardPassResult {
+                activated: input.clone(),
+                cache: Matrix::zeros(0, 0),
+            },
+        }
+    }
+
+    fn backward(
+        &self,
+        _input: &Matrix,
+        result: &ForwardPassResult,
+        output_gradient: &Matrix,
+    ) -> Matrix {
+        if result.cache.items.is_empty() {
+            output_gradient.clone()
+        } else {
+            output_gradient.hadamard(&result.cache)
+        }
     }
 
-    pub fn initialize(&mut self, weights: Matrix, is_output: bool) {
-        self.set_weights(weights);
-        self.is_output = is_output;
+    fn key(&self) -> Option<LayerKey> {
+        Some(LayerKey::Dropout(self.rate))
     }
 }
 
-pub fn create_layer(
-    count: u32,
-    activation: activation::ActivationFunctionKey,
-    error: error::ErrorFunctionKey,
-) -> Layer {
-    let error_fn = match error {
-        error::ErrorFunctionKey::CrossEntropy => error::CE_LOSS,
-        error::ErrorFunctionKey::LogLoss => error::LOG_LOSS,
-        error::ErrorFunctionKey::MeanSquared => error::MSE_LOSS,
-    };
-    let activation_fn = match activation {
-        activation::ActivationFunctionKey::ReLu => activation::RELU_ACTIVATION,
-        activation::ActivationFunctionKey::Sigmoid => activation::SIGMOID_ACTIVATION,
-        activation::ActivationFunctionKey::TanH => activation::TANH_ACTIVATION,
-        activation::ActivationFunctionKey::Softmax => activation::SOFTMAX_ACTIVATION,
-    };
-    Layer {
-        is_output: false,
-        activation: activation_fn,
-        activation_key: activation,
-        error: error_fn,
-        error_key: error,
-        neuron_count: count,
-        weights: Matrix::from(&vec![0.0]),
-        weights_transpose: Matrix::from(&vec![0.0]),
+/// Turns spatial input into a flat vector.
+pub struct Flatten;
+
+impl Layer for Flatten {
+    fn name(&self) -> String {
+        "Flatten".to_string()
+    }
+
+    fn output_shape(&self, input: Shape) -> Result<Shape, String> {
+        Ok(Shape::flat(input.size()))
+    }
+
+    fn forward(&self, input: &Matrix, _rng: Option<&mut SeededRng>) -> ForwardPassResult {
+        ForwardPassResult {
+            activated: input.clone(),
+            cache: Matrix::zeros(0, 0),
+        }
+    }
+
+    fn backward(
+        &self,
+        _input: &Matrix,
+        _result: &ForwardPassResult,
+        output_gradient: &Matrix,
+    ) -> Matrix {
+        output_gradient.clone()
+    }
+
+    fn key(&self) -> Option<LayerKey> {
+        Some(LayerKey::Flatten)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dense_prepends_bias_to_its_input() {
+        let mut layer = Dense::new(2, ActivationFunctionKey::ReLu);
+        layer.set_parameters(Matrix::from(&vec![vec![1.0, 2.0], vec![-1.0, 1.0]]));
+
+        let input = Matrix::from(&vec![0.5]);
+        let result = layer.forward(&input, None);
+        assert_eq!(result.cache.items, vec![2.0, -0.5]);
+        assert_eq!(result.activated.items, vec![2.0, 0.0]);
+
+        // With respect to the sums, after the ReLU's gradient.
+        let gradient = Matrix::from(&vec![1.0, 0.0]);
+        assert_eq!(layer.backward(&input, &result, &gradient).items, vec![2.0]);
+        let mut total = Matrix::zeros(2, 2);
+        layer.gradients(&input, &result, &gradient, &mut total);
+        assert_eq!(total.items, vec![1.0, 0.5, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn dropout_only_applies_while_training() {
+        let layer = Dropout::new(0.5);
+        let input = Matrix::from(&vec![1.0; 64]);
+        assert_eq!(layer.forward(&input, None).activated, input);
+
+        let mut rng = SeededRng::from_seed(1);
+        let result = layer.forward(&input, Some(&mut rng));
+        assert!(result.activated.items.iter().all(|&x| x == 0.0 || x == 2.0));
+        assert!(result.activated.items.contains(&0.0));
+        let gradient = layer.backward(&input, &result, &input);
+        assert_eq!(gradient, result.activated);
+    }
+
+    #[test]
+    #[should_panic(expected = "Flatten has no parameters")]
+    fn parameterless_layers_reject_parameters() {
+        Flatten.set_parameters(Matrix::from(&vec![1.0]));
     }
 }
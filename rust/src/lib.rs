@@ -0,0 +1,26 @@
+pub mod activation;
+pub mod builder;
+pub mod checkpoint;
+pub mod conv;
+pub mod data_loader;
+pub mod early_stopping;
+pub mod error;
+pub mod experiment;
+pub mod gradient_check;
+pub mod history;
+pub mod idx;
+pub mod initializer;
+pub mod layer;
+pub mod matrix;
+pub mod metrics;
+pub mod network;
+pub mod optimizer;
+pub mod pooling;
+pub mod preprocessing;
+pub mod random;
+pub mod regularizer;
+pub mod sampling;
+pub mod schedule;
+pub mod summary;
+pub mod utils;
+pub mod validation;
@@ -1,122 +1,197 @@
-mod activation;
-mod error;
-mod layer;
-mod matrix;
-mod network;
-mod optimizer;
-mod utils;
-
-use activation::ActivationFunctionKey;
-use error::ErrorFunctionKey;
-use network::create_network;
-use utils::{read_lines, DataSetItem};
-
-use crate::{
-    optimizer::{sgd_optimizer, Optimizer},
-    utils::{generate_batch, is_correct_category, one_hot_encode},
-};
+use rust::activation::ActivationFunctionKey;
+use rust::builder::NetworkBuilder;
+use rust::checkpoint::{save_network_to_file, Checkpoint};
+use rust::data_loader::DataLoader;
+use rust::early_stopping::{EarlyStopping, MonitorKey, ReduceLrOnPlateau};
+use rust::error::ErrorFunctionKey;
+use rust::experiment::{Experiment, OptimizerConfig};
+use rust::history::{save_results, TrainingHistory};
+use rust::network::{self, create_network};
+use rust::utils::{read_csv, CsvSchema, DataSetItem};
+
+use rust::optimizer::{sgd_optimizer, Optimizer};
+use rust::schedule::{Constant, InverseTimeDecay};
+use rust::validation::{evaluate, Evaluation};
+
+const LABEL_SIZE: usize = 10;
 
 fn main() {
     // run_xor();
-    run_mnist()
+    match std::env::args().nth(1).as_deref() {
+        Some("--compare-optimizers") => run_optimizer_comparison(),
+        Some("--resume") => run_mnist(true),
+        _ => run_mnist(false),
+    }
 }
 
-fn run_mnist() {
-    const LABEL_SIZE: usize = 10;
-    const BATCH_SIZE: u32 = 200;
-    const EPOCHS: u64 = 40;
-
-    fn read_data(path: &str) -> Vec<DataSetItem> {
-        let mut result = Vec::new();
-        if let Ok(lines) = read_lines(path.to_string()) {
-            // Consumes the iterator, returns an (Optional) String
-            for line in lines {
-                if let Ok(inp) = line {
-                    if inp.len() == 0 {
-                        continue;
-                    }
-                    let mut items: Vec<f64> = inp
-                        .split(",")
-                        .map(|x| -> f64 { x.parse::<f64>().unwrap() })
-                        .collect();
-                    let output = one_hot_encode(items.remove(0), LABEL_SIZE);
-                    result.push(DataSetItem {
-                        input: items,
-                        output,
-                    })
-                }
-            }
+fn read_mnist(path: &str) -> Vec<DataSetItem> {
+    let schema = CsvSchema {
+        one_hot_classes: Some(LABEL_SIZE),
+        ..CsvSchema::default()
+    };
+    match read_csv(path, &schema) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", path, e);
+            std::process::exit(1);
         }
-        result
     }
+}
 
-    fn lr_schedule(i: u64) -> f64 {
-        let initial = 0.5;
-        let decay = 0.1;
-        let min = 0.05;
-
-        let result = initial * (1.0 / (1.0 + decay * i as f64));
-
-        result.max(min)
+/// Trains from scratch, or with `resume` from the checkpoint of an
+/// interrupted run.
+fn run_mnist(resume: bool) {
+    const BATCH_SIZE: usize = 200;
+    const EPOCHS: u64 = 40;
+    const HISTORY_FILE: &str = "./results-rust.json";
+    const CHECKPOINT_FILE: &str = "./mnist_checkpoint.txt";
+    const MODEL_FILE: &str = "./mnist_model.txt";
+
+    fn validate(
+        network: &network::Network,
+        train_data: &[DataSetItem],
+        test_data: &[DataSetItem],
+        history: &mut TrainingHistory,
+        iteration: u64,
+    ) -> Evaluation {
+        let train = evaluate(network, train_data);
+        let test = evaluate(network, test_data);
+        println!("\nValidation loss: {}", test.loss);
+        println!("     percentage: {}", test.accuracy);
+        history.record(iteration, train, test);
+        test
     }
 
-    fn validate(network: &network::Network, data: &Vec<DataSetItem>) {
-        let mut correct_count = 0;
-        let mut total_loss = 0.0;
-        for item in data {
-            let result = network.predict(&item);
-            let last = &result.results[result.results.len() - 1];
-
-            let is_correct = is_correct_category(&last.activated, &item.output);
-            total_loss += last.error;
-            if is_correct {
-                correct_count += 1;
-            }
+    let train_data = read_mnist("./mnist_train.csv");
+    let test_data = read_mnist("./mnist_test.csv");
+
+    let mut loader = DataLoader::new(train_data, BATCH_SIZE, true);
+
+    let built = NetworkBuilder::new(loader.items()[0].input.len())
+        .loss(ErrorFunctionKey::CrossEntropy)
+        .dense(32)
+        .activation(ActivationFunctionKey::Sigmoid)
+        .dense(LABEL_SIZE as u32)
+        .activation(ActivationFunctionKey::Softmax)
+        .build();
+    let mut network = match built {
+        Ok(network) => network,
+        Err(e) => {
+            eprintln!("Invalid network: {}", e);
+            std::process::exit(1);
         }
-        println!("\nValidation loss: {}", total_loss / data.len() as f64);
-        println!(
-            "     percentage: {}",
-            correct_count as f64 / data.len() as f64
-        );
-    }
-
-    let train_data = read_data("./mnist_train.csv");
-    let test_data = read_data("./mnist_test.csv");
-
-    let set_size = train_data.len();
-    let epoch_iterations = (set_size as f64 / BATCH_SIZE as f64).floor() as u64;
-
-    let iterations = epoch_iterations * EPOCHS;
-
-    let mut network = create_network(ErrorFunctionKey::CrossEntropy);
-    network.add(ActivationFunctionKey::Sigmoid, 32);
-    network.add(ActivationFunctionKey::Softmax, 10);
-    network.initialize(generate_batch(&train_data, BATCH_SIZE), None);
+    };
+    println!("{}", network.summary());
 
     use std::time::Instant;
     let now = Instant::now();
 
-    let mut optimizer = sgd_optimizer(None, Some(lr_schedule));
-    for i in 0..iterations {
-        let data = network.compute_gradients();
-
-        optimizer.do_update(&data, &mut network, i);
-        network.set_data(generate_batch(&train_data, BATCH_SIZE));
-
-        if i % 10 == 0 {
-            println!("Iter {}: time taken {:.2?}", i, now.elapsed().as_millis());
-            println!("   - loss: {}", data.loss);
+    let mut optimizer = sgd_optimizer(InverseTimeDecay {
+        initial: 0.5,
+        decay: 0.1,
+        min: 0.05,
+    });
+    let mut plateau = ReduceLrOnPlateau::new(MonitorKey::Loss, 0.5, 2);
+    let mut early_stopping = EarlyStopping::new(MonitorKey::Loss, 5, 1e-4, true);
+    let mut history = TrainingHistory::new("SGD");
+    let mut i = 0;
+    if resume {
+        let restored = Checkpoint::load_from_file(CHECKPOINT_FILE).and_then(|checkpoint| {
+            let iteration = checkpoint.restore(&mut network, &mut optimizer, &mut loader)?;
+            checkpoint.restore_progress(&mut plateau, &mut early_stopping, &mut history);
+            Ok(iteration)
+        });
+        match restored {
+            Ok(iteration) => {
+                i = iteration;
+                println!("Resuming from iteration {}", i);
+            }
+            Err(e) => {
+                eprintln!("Can not resume from {}: {}", CHECKPOINT_FILE, e);
+                std::process::exit(1);
+            }
         }
-
-        if i > 0 && i % epoch_iterations == 0 {
-            validate(&network, &test_data)
+    } else {
+        validate(&network, loader.items(), &test_data, &mut history, i);
+    }
+    let first_epoch = i / loader.batch_count() as u64;
+    for _ in first_epoch..EPOCHS {
+        for batch in loader.epoch(&mut network.rng) {
+            let data = network.compute_gradients(batch);
+            optimizer.do_update(&data, &mut network, i);
+
+            if i % 10 == 0 {
+                println!("Iter {}: time taken {:.2?}", i, now.elapsed().as_millis());
+                println!("   - loss: {}", data.loss);
+            }
+            i += 1;
+        }
+        let result = validate(&network, loader.items(), &test_data, &mut history, i);
+        if plateau.update(&result, &mut optimizer) {
+            println!("Learning rate scaled to {}", optimizer.learning_rate_scale);
+        }
+        if early_stopping.update(&result, &mut network) {
+            println!("Stopping early at iteration {}", i);
+            break;
+        }
+        let checkpoint = Checkpoint::capture(&network, &optimizer, &loader, i)
+            .with_plateau(&plateau)
+            .with_early_stopping(&early_stopping)
+            .with_history(&history);
+        if let Err(e) = checkpoint.save_to_file(CHECKPOINT_FILE) {
+            eprintln!("Failed to write {}: {}", CHECKPOINT_FILE, e);
         }
     }
-    validate(&network, &test_data);
+
+    if let Err(e) = save_results(HISTORY_FILE, &[history]) {
+        eprintln!("Failed to write {}: {}", HISTORY_FILE, e);
+    }
+    if let Err(e) = save_network_to_file(&network, MODEL_FILE) {
+        eprintln!("Failed to write {}: {}", MODEL_FILE, e);
+    }
 
     println!("Elapsed: {:.2?}", now.elapsed().as_millis());
 }
 
+fn run_optimizer_comparison() {
+    let train_data = read_mnist("./mnist_train.csv");
+    let test_data = read_mnist("./mnist_test.csv");
+
+    let build = || {
+        let mut network = create_network(ErrorFunctionKey::CrossEntropy);
+        network.add(ActivationFunctionKey::Sigmoid, 32);
+        network.add(ActivationFunctionKey::Softmax, LABEL_SIZE as u32);
+        network
+    };
+    let optimizers = [
+        OptimizerConfig::new("SGD", || Box::new(sgd_optimizer(Constant(0.1)))),
+        OptimizerConfig::new("Momentum", || {
+            let mut optimizer = sgd_optimizer(Constant(0.1));
+            optimizer.momentum = 0.5;
+            Box::new(optimizer)
+        }),
+    ];
+    let experiment = Experiment {
+        epochs: 10,
+        batch_size: 200,
+        seeds: vec![1, 2, 3],
+    };
+
+    let result = experiment.run(build, &optimizers, &train_data, &test_data);
+    for history in &result.averaged {
+        if let Some(best) = history.best() {
+            println!(
+                "{}: best test loss {} ({}) at iteration {}, took {:.2}s",
+                history.optimizer, best.test.loss, best.test.accuracy, best.iteration, history.took
+            );
+        }
+    }
+    if let Err(e) = result.save(".") {
+        eprintln!("Failed to write results: {}", e);
+    }
+}
+
+#[allow(dead_code)]
 fn run_xor() {
     let w1 = vec![vec![0.5, 0.5, 0.5], vec![-0.5, -0.5, -0.5]];
     let w2 = vec![vec![-0.5, 0.5, 0.5]];
@@ -143,23 +218,24 @@ fn run_xor() {
     let mut network = create_network(ErrorFunctionKey::LogLoss);
     network.add(ActivationFunctionKey::TanH, 2);
     network.add(ActivationFunctionKey::Sigmoid, 1);
-    network.initialize(dataset, Some(vec![w1, w2]));
+    network.initialize(2, Some(vec![w1, w2]));
 
-    let mut optimizer = sgd_optimizer(Some(1.0), None);
+    let mut optimizer = sgd_optimizer(Constant(1.0));
 
     use std::time::Instant;
     let now = Instant::now();
 
     for i in 0..300 {
-        let data = network.compute_gradients();
+        let data = network.compute_gradients(&dataset);
         optimizer.do_update(&data, &mut network, i);
     }
 
-    let result = network.compute_gradients();
+    let result = network.compute_gradients(&dataset);
     println!("Elapsed: {:.2?}", now.elapsed().as_micros());
     println!("{}", result.loss);
 
+    println!("{}", network.summary());
     for l in network.layers {
-        l.weights.print()
+        l.parameters().print()
     }
 }
@@ -1,6 +1,6 @@
 use std::convert::From;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Matrix {
     pub items: Vec<f64>,
     pub rows: usize,
@@ -8,6 +8,10 @@ pub struct Matrix {
 }
 
 impl Matrix {
+    pub fn zeros(rows: usize, cols: usize) -> Matrix {
+        create_matrix(vec![0_f64; rows * cols], rows, cols)
+    }
+
     pub fn get(&self, i: usize, j: usize) -> f64 {
         self.items[calc_index(i, j, self.cols)]
     }
@@ -63,19 +67,19 @@ impl Matrix {
     }
 
     pub fn sum(&self, matrix: &Matrix) -> Matrix {
-        return self.map(&|item: f64, i: usize, j: usize| -> f64 { item + matrix.get(i, j) });
+        self.map(&|item: f64, i: usize, j: usize| -> f64 { item + matrix.get(i, j) })
     }
 
     pub fn subtract(&self, matrix: &Matrix) -> Matrix {
-        return self.map(&|item: f64, i: usize, j: usize| -> f64 { item - matrix.get(i, j) });
+        self.map(&|item: f64, i: usize, j: usize| -> f64 { item - matrix.get(i, j) })
     }
 
     pub fn hadamard(&self, matrix: &Matrix) -> Matrix {
-        return self.map(&|item: f64, i: usize, j: usize| -> f64 { item * matrix.get(i, j) });
+        self.map(&|item: f64, i: usize, j: usize| -> f64 { item * matrix.get(i, j) })
     }
 
     pub fn scale(&self, value: f64) -> Matrix {
-        return self.map(&|_, i: usize, j: usize| -> f64 { value * self.get(i, j) });
+        self.map(&|_, i: usize, j: usize| -> f64 { value * self.get(i, j) })
     }
 
     pub fn map(&self, mapper: &dyn Fn(f64, usize, usize) -> f64) -> Matrix {
@@ -98,14 +102,13 @@ impl Matrix {
         if self.cols != 1 {
             panic!("Can only unshift vertical vectors!")
         }
-        let mut result: Vec<f64> = vec![0_f64; self.rows * (self.cols + 1)];
+        let mut result: Vec<f64> = vec![0_f64; self.rows + 1];
         result[0] = value;
-        for i in 0..self.rows * self.cols {
-            result[i + 1] = self.items[i]
-        }
+        result[1..].copy_from_slice(&self.items);
         create_matrix(result, self.rows + 1, self.cols)
     }
 
+    #[allow(clippy::needless_range_loop)]
     pub fn to_arrays(&self) -> Vec<Vec<f64>> {
         let mut result: Vec<Vec<f64>> = vec![vec![0_f64; self.cols]; self.rows];
         for i in 0..self.rows {
@@ -126,11 +129,7 @@ impl From<&Vec<f64>> for Matrix {
         let rows = items.len();
         let cols = 1;
 
-        let mut result: Vec<f64> = vec![0_f64; rows];
-        for i in 0..rows {
-            result[i] = items[i]
-        }
-        create_matrix(result, rows, cols)
+        create_matrix(items.clone(), rows, cols)
     }
 }
 
@@ -157,3 +156,99 @@ fn create_matrix(items: Vec<f64>, rows: usize, cols: usize) -> Matrix {
 fn calc_index(i: usize, j: usize, cols: usize) -> usize {
     i * cols + j
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Matrix {
+        Matrix::from(&vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]])
+    }
+
+    #[test]
+    fn get_and_set() {
+        let mut m = sample();
+        assert_eq!(m.get(1, 2), 6.0);
+        m.set(1, 2, -1.0);
+        assert_eq!(m.get(1, 2), -1.0);
+        assert_eq!(m.items, vec![1.0, 2.0, 3.0, 4.0, 5.0, -1.0]);
+    }
+
+    #[test]
+    fn from_vectors() {
+        let column = Matrix::from(&vec![1.0, 2.0, 3.0]);
+        assert_eq!((column.rows, column.cols), (3, 1));
+        assert_eq!(column.items, vec![1.0, 2.0, 3.0]);
+
+        let m = sample();
+        assert_eq!((m.rows, m.cols), (2, 3));
+        assert_eq!(
+            m.to_arrays(),
+            vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]]
+        );
+    }
+
+    #[test]
+    fn multiply() {
+        let other = Matrix::from(&vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![1.0, 1.0]]);
+        let result = sample().multiply(&other);
+        assert_eq!((result.rows, result.cols), (2, 2));
+        assert_eq!(result.items, vec![4.0, 5.0, 10.0, 11.0]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn multiply_dimension_mismatch() {
+        sample().multiply(&sample());
+    }
+
+    #[test]
+    fn transpose() {
+        let result = sample().transpose();
+        assert_eq!((result.rows, result.cols), (3, 2));
+        assert_eq!(result.items, vec![1.0, 4.0, 2.0, 5.0, 3.0, 6.0]);
+    }
+
+    #[test]
+    fn omit() {
+        let result = sample().omit(0);
+        assert_eq!((result.rows, result.cols), (2, 2));
+        assert_eq!(result.items, vec![2.0, 3.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn elementwise_operations() {
+        let m = sample();
+        let ones = m.map(&|_, _, _| 1.0);
+        assert_eq!(m.sum(&ones).items, vec![2.0, 3.0, 4.0, 5.0, 6.0, 7.0]);
+        assert_eq!(m.subtract(&ones).items, vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(m.hadamard(&m).items, vec![1.0, 4.0, 9.0, 16.0, 25.0, 36.0]);
+        assert_eq!(m.scale(0.5).items, vec![0.5, 1.0, 1.5, 2.0, 2.5, 3.0]);
+    }
+
+    #[test]
+    fn map_passes_indices() {
+        let result = sample().map(&|_, i, j| (i * 10 + j) as f64);
+        assert_eq!(result.items, vec![0.0, 1.0, 2.0, 10.0, 11.0, 12.0]);
+    }
+
+    #[test]
+    fn iterate_visits_first_column() {
+        let mut visited = Vec::new();
+        Matrix::from(&vec![3.0, 4.0]).iterate(&mut |value, i| visited.push((value, i)));
+        assert_eq!(visited, vec![(3.0, 0), (4.0, 1)]);
+    }
+
+    #[test]
+    fn unshift() {
+        let result = Matrix::from(&vec![2.0, 3.0]).unshift(1.0);
+        assert_eq!((result.rows, result.cols), (3, 1));
+        assert_eq!(result.items, vec![1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn unshift_requires_column_vector() {
+        sample().unshift(1.0);
+    }
+}
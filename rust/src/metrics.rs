@@ -0,0 +1,461 @@
+use std::io::{self, Write};
+
+use crate::network::Network;
+use crate::utils::{argmax, binary_class, DataSetItem};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prediction {
+    pub estimate: Vec<f64>,
+    pub output: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClassMetrics {
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+    pub support: usize,
+}
+
+pub struct ClassificationReport {
+    pub accuracy: f64,
+    pub classes: Vec<ClassMetrics>,
+    pub macro_average: ClassMetrics,
+    pub micro_average: ClassMetrics,
+    pub weighted_average: ClassMetrics,
+}
+
+/// Counts indexed as `counts[actual][predicted]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfusionMatrix {
+    pub counts: Vec<Vec<usize>>,
+}
+
+pub fn predictions(network: &Network, data: &[DataSetItem]) -> Vec<Prediction> {
+    data.iter()
+        .map(|item| Prediction {
+            estimate: network.predict(&item.input),
+            output: item.output.clone(),
+        })
+        .collect()
+}
+
+/// Argmax accuracy for multi-class outputs.
+pub fn classification_accuracy(points: &[Prediction]) -> f64 {
+    let correct = points
+        .iter()
+        .filter(|p| argmax(&p.estimate) == argmax(&p.output))
+        .count();
+    correct as f64 / points.len() as f64
+}
+
+/// Accuracy of a single-output classifier that predicts 1 above `cutoff`.
+pub fn binary_classification_accuracy(points: &[Prediction], cutoff: f64) -> f64 {
+    let correct = points
+        .iter()
+        .filter(|p| binary_class(p.estimate[0], cutoff) == binary_class(p.output[0], 0.5))
+        .count();
+    correct as f64 / points.len() as f64
+}
+
+/// Share of points whose label is among the `k` highest estimates.
+pub fn top_k_accuracy(points: &[Prediction], k: usize) -> f64 {
+    let correct = points
+        .iter()
+        .filter(|p| {
+            let label = argmax(&p.output);
+            let better = p
+                .estimate
+                .iter()
+                .enumerate()
+                .filter(|(i, x)| {
+                    **x > p.estimate[label] || (**x == p.estimate[label] && *i < label)
+                })
+                .count();
+            better < k
+        })
+        .count();
+    correct as f64 / points.len() as f64
+}
+
+impl ConfusionMatrix {
+    /// Panics unless every estimate and label has `classes` values.
+    pub fn new(points: &[Prediction], classes: usize) -> ConfusionMatrix {
+        let mut counts = vec![vec![0; classes]; classes];
+        for (i, p) in points.iter().enumerate() {
+            if p.estimate.len() != classes || p.output.len() != classes {
+                panic!(
+                    "Prediction {} has {} estimates and {} labels, expected {} classes",
+                    i,
+                    p.estimate.len(),
+                    p.output.len(),
+                    classes
+                );
+            }
+            counts[argmax(&p.output)][argmax(&p.estimate)] += 1;
+        }
+        ConfusionMatrix { counts }
+    }
+
+    pub fn binary(points: &[Prediction], cutoff: f64) -> ConfusionMatrix {
+        let mut counts = vec![vec![0; 2]; 2];
+        for p in points {
+            counts[binary_class(p.output[0], 0.5)][binary_class(p.estimate[0], cutoff)] += 1;
+        }
+        ConfusionMatrix { counts }
+    }
+
+    pub fn classes(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts
+            .iter()
+            .map(|row| row.iter().sum::<usize>())
+            .sum()
+    }
+
+    pub fn accuracy(&self) -> f64 {
+        let correct: usize = (0..self.classes()).map(|i| self.counts[i][i]).sum();
+        ratio(correct, self.total())
+    }
+
+    pub fn class_metrics(&self, class: usize) -> ClassMetrics {
+        let true_positives = self.counts[class][class];
+        let predicted: usize = self.counts.iter().map(|row| row[class]).sum();
+        let support: usize = self.counts[class].iter().sum();
+        metrics(true_positives, predicted, support)
+    }
+
+    pub fn report(&self) -> ClassificationReport {
+        let classes: Vec<ClassMetrics> =
+            (0..self.classes()).map(|c| self.class_metrics(c)).collect();
+        let total = self.total();
+        let n = classes.len() as f64;
+
+        let macro_average = ClassMetrics {
+            precision: classes.iter().map(|c| c.precision).sum::<f64>() / n,
+            recall: classes.iter().map(|c| c.recall).sum::<f64>() / n,
+            f1: classes.iter().map(|c| c.f1).sum::<f64>() / n,
+            support: total,
+        };
+        let weight = |c: &ClassMetrics| ratio(c.support, total);
+        let weighted_average = ClassMetrics {
+            precision: classes.iter().map(|c| c.precision * weight(c)).sum(),
+            recall: classes.iter().map(|c| c.recall * weight(c)).sum(),
+            f1: classes.iter().map(|c| c.f1 * weight(c)).sum(),
+            support: total,
+        };
+        let correct: usize = (0..self.classes()).map(|i| self.counts[i][i]).sum();
+        let micro_average = metrics(correct, total, total);
+
+        ClassificationReport {
+            accuracy: self.accuracy(),
+            classes,
+            macro_average,
+            micro_average,
+            weighted_average,
+        }
+    }
+}
+
+/// A point of a threshold sweep. For ROC curves `x` is the false positive
+/// rate and `y` the true positive rate; for precision-recall curves `x` is
+/// recall and `y` precision.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CurvePoint {
+    pub threshold: f64,
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Cumulative (threshold, true positives, false positives) after each group
+/// of tied scores, sorted by decreasing score.
+fn threshold_counts(points: &[Prediction]) -> (Vec<(f64, usize, usize)>, usize, usize) {
+    let mut scored: Vec<(f64, bool)> = points
+        .iter()
+        .map(|p| (p.estimate[0], p.output[0] > 0.5))
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let positives = scored.iter().filter(|(_, label)| *label).count();
+    let negatives = scored.len() - positives;
+
+    let mut counts = Vec::new();
+    let (mut true_positives, mut false_positives) = (0, 0);
+    for (i, (score, label)) in scored.iter().enumerate() {
+        if *label {
+            true_positives += 1;
+        } else {
+            false_positives += 1;
+        }
+        if i + 1 == scored.len() || scored[i + 1].0 != *score {
+            counts.push((*score, true_positives, false_positives));
+        }
+    }
+    (counts, positives, negatives)
+}
+
+/// Exact ROC curve over every distinct score of a single-output classifier.
+pub fn roc_curve(points: &[Prediction]) -> Vec<CurvePoint> {
+    let (counts, positives, negatives) = threshold_counts(points);
+    let mut curve = vec![CurvePoint {
+        threshold: f64::INFINITY,
+        x: 0.0,
+        y: 0.0,
+    }];
+    for (threshold, true_positives, false_positives) in counts {
+        curve.push(CurvePoint {
+            threshold,
+            x: ratio(false_positives, negatives),
+            y: ratio(true_positives, positives),
+        });
+    }
+    curve
+}
+
+/// Area under the ROC curve. Tied scores count as half correct. Returns NaN
+/// when only one class is present.
+pub fn roc_auc(points: &[Prediction]) -> f64 {
+    let curve = roc_curve(points);
+    let positives = points.iter().filter(|p| p.output[0] > 0.5).count();
+    if positives == 0 || positives == points.len() {
+        return f64::NAN;
+    }
+    curve
+        .windows(2)
+        .map(|w| (w[1].x - w[0].x) * (w[0].y + w[1].y) / 2.0)
+        .sum()
+}
+
+pub fn precision_recall_curve(points: &[Prediction]) -> Vec<CurvePoint> {
+    let (counts, positives, _) = threshold_counts(points);
+    let mut curve = vec![CurvePoint {
+        threshold: f64::INFINITY,
+        x: 0.0,
+        y: 1.0,
+    }];
+    for (threshold, true_positives, false_positives) in counts {
+        curve.push(CurvePoint {
+            threshold,
+            x: ratio(true_positives, positives),
+            y: ratio(true_positives, true_positives + false_positives),
+        });
+    }
+    curve
+}
+
+/// Average precision: the precision at each threshold weighted by the
+/// increase in recall, without interpolation.
+pub fn average_precision(points: &[Prediction]) -> f64 {
+    precision_recall_curve(points)
+        .windows(2)
+        .map(|w| (w[1].x - w[0].x) * w[1].y)
+        .sum()
+}
+
+/// Writes a curve as CSV with a `threshold,<x_label>,<y_label>` header.
+pub fn write_curve<W: Write>(
+    writer: &mut W,
+    curve: &[CurvePoint],
+    x_label: &str,
+    y_label: &str,
+) -> io::Result<()> {
+    writeln!(writer, "threshold,{},{}", x_label, y_label)?;
+    for point in curve {
+        writeln!(writer, "{},{},{}", point.threshold, point.x, point.y)?;
+    }
+    Ok(())
+}
+
+fn metrics(true_positives: usize, predicted: usize, support: usize) -> ClassMetrics {
+    let precision = ratio(true_positives, predicted);
+    let recall = ratio(true_positives, support);
+    let f1 = if precision + recall == 0.0 {
+        0.0
+    } else {
+        2.0 * precision * recall / (precision + recall)
+    };
+    ClassMetrics {
+        precision,
+        recall,
+        f1,
+        support,
+    }
+}
+
+fn ratio(a: usize, b: usize) -> f64 {
+    if b == 0 {
+        0.0
+    } else {
+        a as f64 / b as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(estimate: &[f64], output: &[f64]) -> Prediction {
+        Prediction {
+            estimate: estimate.to_vec(),
+            output: output.to_vec(),
+        }
+    }
+
+    fn multi_class() -> Vec<Prediction> {
+        vec![
+            point(&[0.8, 0.1, 0.1], &[1.0, 0.0, 0.0]),
+            point(&[0.6, 0.3, 0.1], &[1.0, 0.0, 0.0]),
+            point(&[0.2, 0.5, 0.3], &[1.0, 0.0, 0.0]),
+            point(&[0.1, 0.7, 0.2], &[0.0, 1.0, 0.0]),
+            point(&[0.5, 0.4, 0.1], &[0.0, 1.0, 0.0]),
+            point(&[0.1, 0.2, 0.7], &[0.0, 0.0, 1.0]),
+        ]
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-12, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn confusion_matrix_counts() {
+        let matrix = ConfusionMatrix::new(&multi_class(), 3);
+        assert_eq!(
+            matrix.counts,
+            vec![vec![2, 1, 0], vec![1, 1, 0], vec![0, 0, 1]]
+        );
+        assert_eq!(matrix.total(), 6);
+        assert_close(matrix.accuracy(), 4.0 / 6.0);
+    }
+
+    #[test]
+    fn per_class_and_averages() {
+        let report = ConfusionMatrix::new(&multi_class(), 3).report();
+
+        let first = report.classes[0];
+        assert_close(first.precision, 2.0 / 3.0);
+        assert_close(first.recall, 2.0 / 3.0);
+        assert_close(first.f1, 2.0 / 3.0);
+        assert_eq!(first.support, 3);
+
+        let second = report.classes[1];
+        assert_close(second.precision, 0.5);
+        assert_close(second.recall, 0.5);
+
+        assert_close(
+            report.macro_average.precision,
+            (2.0 / 3.0 + 0.5 + 1.0) / 3.0,
+        );
+        assert_close(report.micro_average.precision, 4.0 / 6.0);
+        assert_close(report.micro_average.f1, 4.0 / 6.0);
+        assert_close(
+            report.weighted_average.recall,
+            (3.0 * 2.0 / 3.0 + 2.0 * 0.5 + 1.0) / 6.0,
+        );
+        assert_close(report.accuracy, 4.0 / 6.0);
+    }
+
+    #[test]
+    fn empty_classes_do_not_divide_by_zero() {
+        let points = vec![point(&[0.9, 0.1], &[1.0, 0.0])];
+        let report = ConfusionMatrix::new(&points, 2).report();
+        assert_eq!(report.classes[1].precision, 0.0);
+        assert_eq!(report.classes[1].f1, 0.0);
+    }
+
+    #[test]
+    fn accuracies() {
+        let points = multi_class();
+        assert_close(classification_accuracy(&points), 4.0 / 6.0);
+        assert_close(top_k_accuracy(&points, 1), 4.0 / 6.0);
+        assert_close(top_k_accuracy(&points, 2), 5.0 / 6.0);
+        assert_close(top_k_accuracy(&points, 3), 1.0);
+    }
+
+    fn scored(pairs: &[(f64, f64)]) -> Vec<Prediction> {
+        pairs.iter().map(|(e, o)| point(&[*e], &[*o])).collect()
+    }
+
+    #[test]
+    fn roc_curve_and_auc() {
+        let points = scored(&[(0.9, 1.0), (0.8, 0.0), (0.7, 1.0), (0.3, 0.0), (0.1, 0.0)]);
+        let curve = roc_curve(&points);
+        let rates: Vec<(f64, f64)> = curve.iter().map(|p| (p.x, p.y)).collect();
+        assert_eq!(
+            rates,
+            vec![
+                (0.0, 0.0),
+                (0.0, 0.5),
+                (1.0 / 3.0, 0.5),
+                (1.0 / 3.0, 1.0),
+                (2.0 / 3.0, 1.0),
+                (1.0, 1.0)
+            ]
+        );
+        assert_eq!(curve[1].threshold, 0.9);
+        // 5 of the 6 positive/negative pairs are ordered correctly.
+        assert_close(roc_auc(&points), 5.0 / 6.0);
+    }
+
+    #[test]
+    fn roc_auc_counts_ties_as_half() {
+        let points = scored(&[(0.5, 1.0), (0.5, 0.0), (0.9, 1.0), (0.1, 0.0)]);
+        assert_eq!(roc_curve(&points).len(), 4);
+        assert_close(roc_auc(&points), (3.0 + 0.5) / 4.0);
+        assert!(roc_auc(&scored(&[(0.3, 1.0), (0.6, 1.0)])).is_nan());
+    }
+
+    #[test]
+    fn precision_recall_and_average_precision() {
+        let points = scored(&[(0.9, 1.0), (0.8, 0.0), (0.7, 1.0), (0.3, 0.0)]);
+        let curve = precision_recall_curve(&points);
+        let values: Vec<(f64, f64)> = curve.iter().map(|p| (p.x, p.y)).collect();
+        assert_eq!(
+            values,
+            vec![
+                (0.0, 1.0),
+                (0.5, 1.0),
+                (0.5, 0.5),
+                (1.0, 2.0 / 3.0),
+                (1.0, 0.5)
+            ]
+        );
+        assert_close(average_precision(&points), 0.5 * 1.0 + 0.5 * 2.0 / 3.0);
+    }
+
+    #[test]
+    fn curves_export_as_csv() {
+        let curve = roc_curve(&scored(&[(0.8, 1.0), (0.2, 0.0)]));
+        let mut buffer = Vec::new();
+        write_curve(&mut buffer, &curve, "fpr", "tpr").unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "threshold,fpr,tpr\ninf,0,0\n0.8,0,1\n0.2,1,1\n"
+        );
+    }
+
+    #[test]
+    fn binary_metrics() {
+        let points = vec![
+            point(&[0.9], &[1.0]),
+            point(&[0.4], &[1.0]),
+            point(&[0.6], &[0.0]),
+            point(&[0.1], &[0.0]),
+        ];
+        assert_close(binary_classification_accuracy(&points, 0.5), 0.5);
+        assert_close(binary_classification_accuracy(&points, 0.3), 0.75);
+        assert_close(binary_classification_accuracy(&points, 0.7), 0.75);
+
+        let matrix = ConfusionMatrix::binary(&points, 0.5);
+        assert_eq!(matrix.counts, vec![vec![1, 1], vec![1, 1]]);
+        assert_close(matrix.class_metrics(1).recall, 0.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "Prediction 0 has 3 estimates and 3 labels, expected 2 classes")]
+    fn confusion_matrix_checks_class_count() {
+        ConfusionMatrix::new(&multi_class(), 2);
+    }
+}
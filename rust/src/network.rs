@@ -1,173 +1,455 @@
-use crate::layer::Layer;
+use std::cell::RefCell;
+
+use crate::activation::{activation_function, ActivationFunctionKey};
+use crate::conv::{Conv2d, Window};
+use crate::error::{self, error_function, output_delta};
+use crate::initializer::InitializerKey;
+use crate::layer::{Dense, Dropout, Flatten, ForwardPassResult, Layer, Shape};
 use crate::matrix::Matrix;
-use crate::optimizer::Optimizer;
-use crate::{activation, layer};
-use crate::{
-    error,
-    utils::{random_weights, DataSetItem},
-};
+use crate::pooling::{GlobalAvgPool, Pool2d, PoolKey};
+use crate::random::SeededRng;
+use crate::summary::{summarize, Summary};
+use crate::utils::{argmax, binary_class, DataSetItem};
 
 pub struct Network {
     error: error::ErrorFunctionKey,
-    pub dataset: Vec<DataSetItem>,
-    pub layers: Vec<Layer>,
+    input_shape: Shape,
+    pub layers: Vec<Box<dyn Layer>>,
+    pub rng: SeededRng,
+    /// Passed to the layers during training, e.g. to draw dropout masks.
+    /// Kept apart from `rng` so that gradients can be computed through a
+    /// shared reference.
+    dropout_rng: RefCell<SeededRng>,
 }
 
 pub struct ForwardResult {
-    pub results: Vec<layer::ForwardPassResult>,
-    pub activated_input: Matrix,
+    pub results: Vec<ForwardPassResult>,
+    pub input: Matrix,
+    pub loss: f64,
 }
 
 pub struct GradientResult {
     pub gradients: Vec<Matrix>,
     pub loss: f64,
+    /// Global L2 norm of `gradients`, before any clipping by the optimizer.
+    pub gradient_norm: f64,
+}
+
+/// L2 norm of all gradient elements across every layer.
+pub fn global_norm(gradients: &[Matrix]) -> f64 {
+    gradients
+        .iter()
+        .flat_map(|g| g.items.iter())
+        .map(|x| x * x)
+        .sum::<f64>()
+        .sqrt()
 }
 
 pub fn create_network(error: error::ErrorFunctionKey) -> Network {
     Network {
         error,
-        dataset: Vec::new(),
+        input_shape: Shape::flat(0),
         layers: Vec::new(),
+        rng: SeededRng::from_entropy(),
+        dropout_rng: RefCell::new(SeededRng::from_entropy()),
     }
 }
 
 impl Network {
-    pub fn set_data(&mut self, dataset: Vec<DataSetItem>) {
-        self.dataset = dataset;
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = SeededRng::from_seed(seed);
+        self.dropout_rng = RefCell::new(SeededRng::from_seed(!seed));
     }
 
-    pub fn add(&mut self, activation: activation::ActivationFunctionKey, count: u32) {
-        self.layers
-            .push(layer::create_layer(count, activation, self.error))
+    pub fn dropout_rng_state(&self) -> [u64; 4] {
+        self.dropout_rng.borrow().state()
+    }
+
+    pub fn set_dropout_rng_state(&mut self, state: [u64; 4]) {
+        self.dropout_rng = RefCell::new(SeededRng::from_state(state));
+    }
+
+    pub fn error(&self) -> error::ErrorFunctionKey {
+        self.error
+    }
+
+    /// Number of input features, known once the network is initialized.
+    pub fn input_dim(&self) -> usize {
+        self.input_shape.size()
+    }
+
+    pub fn input_shape(&self) -> Shape {
+        self.input_shape
+    }
+
+    /// Appends any layer, including user-defined ones.
+    pub fn add_layer(&mut self, layer: impl Layer + 'static) {
+        self.layers.push(Box::new(layer))
+    }
+
+    pub fn add(&mut self, activation: ActivationFunctionKey, count: u32) {
+        self.add_layer(Dense::new(count as usize, activation))
+    }
+
+    pub fn add_with_initializer(
+        &mut self,
+        activation: ActivationFunctionKey,
+        count: u32,
+        initializer: InitializerKey,
+    ) {
+        let mut layer = Dense::new(count as usize, activation);
+        layer.initializer = initializer;
+        self.add_layer(layer)
+    }
+
+    /// Adds a 2D convolution with `filters` output channels. The network
+    /// must be initialized with `initialize_with_shape`.
+    pub fn add_conv2d(&mut self, activation: ActivationFunctionKey, filters: u32, window: Window) {
+        self.add_layer(Conv2d::new(filters as usize, window, activation))
+    }
+
+    pub fn add_flatten(&mut self) {
+        self.add_layer(Flatten)
     }
 
-    pub fn compute_gradients(&self) -> GradientResult {
+    /// Adds a 2D pooling layer. Pooling windows should not be padded.
+    pub fn add_pool2d(&mut self, pool: PoolKey, window: Window) {
+        self.add_layer(Pool2d::new(pool, window))
+    }
+
+    pub fn add_global_avg_pool(&mut self) {
+        self.add_layer(GlobalAvgPool::new())
+    }
+
+    /// Drops a `rate` fraction of the previous layer's outputs during
+    /// training.
+    pub fn add_dropout(&mut self, rate: f64) {
+        self.add_layer(Dropout::new(rate))
+    }
+
+    /// Mean gradients and loss over `batch`, including weight penalties.
+    /// Panics on an empty batch, which has no mean.
+    pub fn compute_gradients(&self, batch: &[DataSetItem]) -> GradientResult {
+        if batch.is_empty() {
+            panic!("Can not compute gradients of an empty batch");
+        }
         let mut total_gradient = Vec::new();
         for layer in &self.layers {
-            total_gradient.push(layer.weights.scale(0.0))
+            total_gradient.push(layer.parameters().scale(0.0))
         }
 
         let mut total_loss = 0.0;
 
-        for data in &self.dataset {
+        for data in batch {
             let data = self.compute_result(
                 &Matrix::from(&data.input),
                 &Matrix::from(&data.output),
                 &mut total_gradient,
             );
-            let results = data.results;
-            total_loss += results[results.len() - 1].error / self.dataset.len() as f64
+            total_loss += data.loss / batch.len() as f64
         }
 
+        let scale = 1.0 / batch.len() as f64;
+        let gradients: Vec<Matrix> = total_gradient
+            .iter()
+            .zip(&self.layers)
+            .map(|(g, layer)| match layer.regularizer() {
+                Some(regularizer) => g
+                    .scale(scale)
+                    .sum(&regularizer.gradient(layer.parameters())),
+                None => g.scale(scale),
+            })
+            .collect();
         GradientResult {
-            gradients: total_gradient,
-            loss: total_loss,
+            gradient_norm: global_norm(&gradients),
+            gradients,
+            loss: total_loss + self.regularization_loss(),
         }
     }
 
+    /// Sum of the weight penalties of all regularized layers.
+    pub fn regularization_loss(&self) -> f64 {
+        self.layers
+            .iter()
+            .filter_map(|layer| layer.regularizer().map(|r| r.penalty(layer.parameters())))
+            .sum()
+    }
+
     pub fn compute_result(
         &self,
         real_input: &Matrix,
         expected: &Matrix,
-        total: &mut Vec<Matrix>,
+        total: &mut [Matrix],
     ) -> ForwardResult {
-        let data = self.forward_pass(real_input, expected);
+        let data = {
+            let mut rng = self.dropout_rng.borrow_mut();
+            self.forward_pass_with_rng(real_input, expected, Some(&mut rng))
+        };
 
-        self.backward_pass(&data.activated_input, expected, &data.results, total);
+        self.backward_pass(&data.input, expected, &data.results, total);
 
         data
     }
 
     pub fn forward_pass(&self, input: &Matrix, expected: &Matrix) -> ForwardResult {
-        let activated_input = (activation::IDENTITY_ACTIVATION.forward)(&input, false);
+        self.forward_pass_with_rng(input, expected, None)
+    }
 
-        let mut results: Vec<layer::ForwardPassResult> = Vec::new();
+    fn forward_pass_with_rng(
+        &self,
+        input: &Matrix,
+        expected: &Matrix,
+        mut rng: Option<&mut SeededRng>,
+    ) -> ForwardResult {
+        let mut results: Vec<ForwardPassResult> = Vec::new();
         for layer in &self.layers {
-            let current_activation = if results.len() > 0 {
-                &results[results.len() - 1].activated
-            } else {
-                &activated_input
+            let current = match results.last() {
+                Some(result) => &result.activated,
+                None => input,
             };
-            let result = layer.forward_pass(current_activation, &expected);
+            let result = layer.forward(current, rng.as_deref_mut());
             results.push(result);
         }
 
+        let output = match results.last() {
+            Some(result) => &result.activated,
+            None => input,
+        };
+        let loss = (error_function(self.error).loss)(output, expected);
         ForwardResult {
             results,
-            activated_input,
+            input: input.clone(),
+            loss,
         }
     }
 
+    /// Adds each layer's parameter gradients to `total`, walking back from
+    /// the gradient of the loss. Activation gradients are applied here, and
+    /// fused with the loss's for the output layer where `output_delta`
+    /// allows it.
     pub fn backward_pass(
         &self,
         input: &Matrix,
         expected: &Matrix,
-        results: &Vec<layer::ForwardPassResult>,
-        total: &mut Vec<Matrix>,
+        results: &[ForwardPassResult],
+        total: &mut [Matrix],
     ) {
-        let result_out = &results[results.len() - 1];
-        let layer_out = &self.layers[self.layers.len() - 1];
-
-        let delta_out = layer_out.output_pass(result_out, &expected);
-        let mut deltas = vec![delta_out];
-
-        for i in (0..self.layers.len() - 1).rev() {
-            let result = &results[i];
-            let delta = self.layers[i].backward_pass(&result, &deltas[0], &self.layers[i + 1]);
-            deltas.insert(0, delta);
-        }
+        let last = self.layers.len() - 1;
+        let output = &results[last].activated;
+        let fused = self.layers[last]
+            .activation()
+            .and_then(|activation| output_delta(self.error, activation));
+        let mut gradient = match fused {
+            Some(delta) => delta(output, expected),
+            None if self.layers[last].activation() == Some(ActivationFunctionKey::Softmax) => {
+                panic!("can only use softmax with cross-entropy")
+            }
+            None => (error_function(self.error).grad)(output, expected),
+        };
 
-        for l in 0..total.len() {
-            let weights = &mut total[l];
-            let activations = if l == 0 {
-                &input
+        for l in (0..self.layers.len()).rev() {
+            let layer_input = if l == 0 {
+                input
             } else {
                 &results[l - 1].activated
             };
-            let delta = &deltas[l];
-
-            for i in 0..weights.rows {
-                for j in 0..weights.cols {
-                    let diff = delta.get(i, 0) * activations.get(j, 0);
-                    let new_val = weights.get(i, j) + (diff / self.dataset.len() as f64);
-                    weights.set(i, j, new_val);
+            let layer = &self.layers[l];
+            if let Some(activation) = layer.activation() {
+                if l != last || fused.is_none() {
+                    let result = &results[l];
+                    gradient = (activation_function(activation).backward)(
+                        &result.cache,
+                        &result.activated,
+                        &gradient,
+                    );
                 }
             }
+            layer.gradients(layer_input, &results[l], &gradient, &mut total[l]);
+            if l > 0 {
+                gradient = layer.backward(layer_input, &results[l], &gradient);
+            }
         }
     }
 
-    pub fn predict(&self, item: &DataSetItem) -> ForwardResult {
+    /// Forward pass over a labelled item, keeping every layer's result and
+    /// the loss.
+    pub fn evaluate_item(&self, item: &DataSetItem) -> ForwardResult {
         let input = Matrix::from(&item.input);
         let output = Matrix::from(&item.output);
         self.forward_pass(&input, &output)
     }
 
-    pub fn initialize(&mut self, dataset: Vec<DataSetItem>, weights: Option<Vec<Vec<Vec<f64>>>>) {
-        self.dataset = dataset;
-        if self.layers.len() == 0 {
-            panic!("No layers provided!")
+    /// Output of the network for one input, without computing a loss or
+    /// keeping intermediate results.
+    pub fn predict(&self, input: &[f64]) -> Vec<f64> {
+        if input.len() != self.input_dim() {
+            panic!("Expected {} inputs, got {}", self.input_dim(), input.len());
         }
-        let mut new_weights: Vec<Vec<Vec<f64>>> = Vec::new();
+        let mut activated = Matrix::from(&input.to_vec());
+        for layer in &self.layers {
+            activated = layer.forward(&activated, None).activated;
+        }
+        activated.items
+    }
 
-        let mut current_dim = self.dataset[0].input.len();
-        if weights.is_none() {
-            for layer in &self.layers {
-                let rows = layer.neuron_count as usize;
-                let cols = current_dim + 1;
+    pub fn predict_batch(&self, inputs: &[Vec<f64>]) -> Vec<Vec<f64>> {
+        inputs.iter().map(|input| self.predict(input)).collect()
+    }
 
-                current_dim = rows;
-                new_weights.push(random_weights(rows, cols))
-            }
-        } else {
-            for w in weights.unwrap() {
-                new_weights.push(w);
+    /// Predicted class: the argmax of multi-output networks, or whether a
+    /// single output is above 0.5.
+    pub fn predict_class(&self, input: &[f64]) -> usize {
+        let output = self.predict(input);
+        if output.len() == 1 {
+            return binary_class(output[0], 0.5);
+        }
+        argmax(&output)
+    }
+
+    /// Class probabilities. A single sigmoid output `p` is expanded to
+    /// `[1 - p, p]`; softmax outputs are returned as they are.
+    pub fn predict_proba(&self, input: &[f64]) -> Vec<f64> {
+        let output = self.predict(input);
+        let activation = self.layers.last().and_then(|layer| layer.activation());
+        match activation {
+            Some(ActivationFunctionKey::Sigmoid) if output.len() == 1 => {
+                vec![1.0 - output[0], output[0]]
             }
+            Some(ActivationFunctionKey::Softmax) => output,
+            key => panic!(
+                "Probabilities need a single sigmoid or a softmax output, got {:?} with {} outputs",
+                key,
+                output.len()
+            ),
         }
+    }
+
+    /// Layer shapes, parameter counts and configuration warnings.
+    pub fn summary(&self) -> Summary {
+        summarize(self)
+    }
 
-        let count = self.layers.len();
-        for l in 0..count {
-            let w = &new_weights[l];
-            self.layers[l].initialize(Matrix::from(w), l == count - 1);
+    /// Initializes the weights for inputs of `input_dim` features, either
+    /// from `weights` or with each layer's initializer.
+    pub fn initialize(&mut self, input_dim: usize, weights: Option<Vec<Vec<Vec<f64>>>>) {
+        self.initialize_with_shape(Shape::flat(input_dim), weights)
+    }
+
+    /// Initializes a network whose input has spatial structure, such as
+    /// images for convolution layers. Panics if a layer does not fit the
+    /// shape of its input or given weights have the wrong dimensions.
+    /// Weights given for layers without parameters are ignored.
+    pub fn initialize_with_shape(&mut self, input: Shape, weights: Option<Vec<Vec<Vec<f64>>>>) {
+        if self.layers.is_empty() {
+            panic!("No layers provided!")
+        }
+        self.input_shape = input;
+        let mut shape = input;
+        for (l, layer) in self.layers.iter_mut().enumerate() {
+            let output = layer
+                .output_shape(shape)
+                .unwrap_or_else(|message| panic!("Layer {}: {}", l, message));
+            layer.initialize(shape, &mut self.rng);
+            let parameters = layer.parameters();
+            if let (Some(weights), false) = (&weights, parameters.items.is_empty()) {
+                let matrix = Matrix::from(&weights[l]);
+                if (matrix.rows, matrix.cols) != (parameters.rows, parameters.cols) {
+                    panic!(
+                        "Layer {} needs {}x{} weights, got {}x{}",
+                        l, parameters.rows, parameters.cols, matrix.rows, matrix.cols
+                    );
+                }
+                layer.set_parameters(matrix);
+            }
+            shape = output;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activation::ActivationFunctionKey;
+    use crate::error::ErrorFunctionKey;
+
+    fn network(output: ActivationFunctionKey, outputs: u32) -> Network {
+        let mut network = create_network(ErrorFunctionKey::CrossEntropy);
+        network.seed(3);
+        network.add(ActivationFunctionKey::TanH, 4);
+        network.add(output, outputs);
+        network.initialize(2, None);
+        network
+    }
+
+    #[test]
+    fn predict_matches_forward_pass() {
+        let network = network(ActivationFunctionKey::Softmax, 3);
+        let item = DataSetItem {
+            input: vec![0.5, -1.5],
+            output: vec![0.0, 1.0, 0.0],
+        };
+        let result = network.evaluate_item(&item);
+        let prediction = network.predict(&item.input);
+        assert_eq!(prediction, result.results[1].activated.items);
+        assert_eq!(network.predict_proba(&item.input), prediction);
+        assert_eq!(network.predict_class(&item.input), argmax(&prediction));
+        assert_eq!(
+            network.predict_batch(&[item.input.clone(), vec![1.0, 1.0]])[0],
+            prediction
+        );
+    }
+
+    #[test]
+    fn single_sigmoid_output_is_binary() {
+        let network = network(ActivationFunctionKey::Sigmoid, 1);
+        let p = network.predict(&[0.2, 0.3])[0];
+        assert_eq!(network.predict_proba(&[0.2, 0.3]), vec![1.0 - p, p]);
+        assert_eq!(network.predict_class(&[0.2, 0.3]), (p > 0.5) as usize);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can not compute gradients of an empty batch")]
+    fn empty_batches_have_no_gradients() {
+        network(ActivationFunctionKey::Sigmoid, 1).compute_gradients(&[]);
+    }
+
+    #[test]
+    #[should_panic(expected = "No layers provided!")]
+    fn initialize_requires_layers() {
+        create_network(ErrorFunctionKey::MeanSquared).initialize(2, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected 2 inputs, got 3")]
+    fn predict_checks_input_size() {
+        network(ActivationFunctionKey::Sigmoid, 1).predict(&[0.0, 1.0, 2.0]);
+    }
+
+    fn saturated_softmax(error: ErrorFunctionKey) -> Network {
+        let mut network = create_network(error);
+        network.add(ActivationFunctionKey::Softmax, 2);
+        network.initialize(1, Some(vec![vec![vec![0.0, 0.0], vec![800.0, 0.0]]]));
+        network
+    }
+
+    #[test]
+    fn softmax_cross_entropy_gradients_stay_finite() {
+        let network = saturated_softmax(ErrorFunctionKey::CrossEntropy);
+        // Logits [0, 800]: the target's probability underflows to 0.
+        let result = network.compute_gradients(&[DataSetItem {
+            input: vec![1.0],
+            output: vec![1.0, 0.0],
+        }]);
+        assert!(result.gradients[0].items.iter().all(|g| g.is_finite()));
+        assert_eq!(result.gradients[0].items, vec![-1.0, -1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "can only use softmax with cross-entropy")]
+    fn softmax_requires_cross_entropy() {
+        saturated_softmax(ErrorFunctionKey::MeanSquared).compute_gradients(&[DataSetItem {
+            input: vec![1.0],
+            output: vec![1.0, 0.0],
+        }]);
+    }
+}
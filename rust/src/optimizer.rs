@@ -1,3 +1,4 @@
+use crate::schedule::LrSchedule;
 use crate::{matrix::Matrix, network};
 
 pub trait Optimizer {
@@ -7,16 +8,76 @@ pub trait Optimizer {
         network: &mut network::Network,
         iteration: u64,
     );
+
+    /// Factor applied on top of the learning rate schedule, adjusted by
+    /// controllers such as `ReduceLrOnPlateau`.
+    fn learning_rate_scale(&self) -> f64;
+
+    fn set_learning_rate_scale(&mut self, scale: f64);
+
+    /// Internal state needed to continue training exactly, e.g. for
+    /// checkpoints.
+    fn state(&self) -> OptimizerState;
+
+    /// Fails, leaving the optimizer unchanged, if `state` was taken from a
+    /// different kind of optimizer.
+    fn set_state(&mut self, state: OptimizerState) -> Result<(), String>;
+}
+
+/// Accumulated optimizer state. `buffers` holds one gradient-shaped set of
+/// matrices per accumulator (velocity, moment estimates, ...) and is empty
+/// before the first update.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptimizerState {
+    pub learning_rate_scale: f64,
+    pub buffers: Vec<Vec<Matrix>>,
+}
+
+/// How gradients are clipped before an update. `Value` clamps every element
+/// to `[-v, v]`; `GlobalNorm` rescales all layers together so that their
+/// combined L2 norm is at most the given value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GradientClipKey {
+    Value(f64),
+    GlobalNorm(f64),
+}
+
+impl GradientClipKey {
+    /// Limits must be finite and positive.
+    pub fn is_valid(self) -> bool {
+        let limit = match self {
+            GradientClipKey::Value(limit) | GradientClipKey::GlobalNorm(limit) => limit,
+        };
+        limit.is_finite() && limit > 0.0
+    }
+}
+
+pub fn clip_gradients(gradients: &[Matrix], clip: GradientClipKey, norm: f64) -> Vec<Matrix> {
+    if !clip.is_valid() {
+        panic!("Invalid gradient clipping {:?}", clip);
+    }
+    match clip {
+        GradientClipKey::Value(limit) => gradients
+            .iter()
+            .map(|g| g.map(&|x, _, _| x.clamp(-limit, limit)))
+            .collect(),
+        GradientClipKey::GlobalNorm(max_norm) => {
+            if norm <= max_norm {
+                return gradients.to_vec();
+            }
+            let scale = max_norm / norm;
+            gradients.iter().map(|g| g.scale(scale)).collect()
+        }
+    }
 }
 
 pub struct GradientDescentOptimizer {
-    pub learning_rate: Option<f64>,
-    pub learning_rate_schedule: Option<fn(u64) -> f64>,
+    pub learning_rate: Box<dyn LrSchedule>,
+    pub learning_rate_scale: f64,
 
     pub momentum: f64,
 
-    is_nesterov: bool,
-    current_weights: Vec<Matrix>,
+    pub clipping: Option<GradientClipKey>,
 
     velocity: Vec<Matrix>,
 
@@ -32,9 +93,8 @@ impl GradientDescentOptimizer {
         self.initialized = true;
     }
 
-    fn update_velocity(&mut self, result: &network::GradientResult, learning_rate: f64) {
-        let gradients = &result.gradients;
-
+    #[allow(clippy::needless_range_loop)]
+    fn update_velocity(&mut self, gradients: &[Matrix], learning_rate: f64) {
         let mut new_vel = Vec::new();
         for i in 0..self.velocity.len() {
             let layer_vel = self.velocity[i]
@@ -56,40 +116,164 @@ impl Optimizer for GradientDescentOptimizer {
         if !self.initialized {
             self.initialize(result)
         }
-        let lr = get_learning_rate(self.learning_rate, self.learning_rate_schedule, iteration);
+        let lr = self.learning_rate.learning_rate(iteration) * self.learning_rate_scale;
+        if lr < 0.0 {
+            panic!("Negative learning rate {} at iteration {}", lr, iteration);
+        }
 
-        self.update_velocity(result, lr);
+        match self.clipping {
+            Some(clip) => {
+                let clipped = clip_gradients(&result.gradients, clip, result.gradient_norm);
+                self.update_velocity(&clipped, lr)
+            }
+            None => self.update_velocity(&result.gradients, lr),
+        }
 
         for i in 0..network.layers.len() {
             let layer = &mut network.layers[i];
-            let weights = &layer.weights;
+            let parameters = layer.parameters().sum(&self.velocity[i]);
+
+            layer.set_parameters(parameters)
+        }
+    }
+
+    fn learning_rate_scale(&self) -> f64 {
+        self.learning_rate_scale
+    }
+
+    fn set_learning_rate_scale(&mut self, scale: f64) {
+        self.learning_rate_scale = scale;
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState {
+            learning_rate_scale: self.learning_rate_scale,
+            buffers: if self.initialized {
+                vec![self.velocity.clone()]
+            } else {
+                Vec::new()
+            },
+        }
+    }
 
-            layer.set_weights(weights.sum(&self.velocity[i]))
+    fn set_state(&mut self, state: OptimizerState) -> Result<(), String> {
+        match state.buffers.len() {
+            0 => {
+                self.velocity = Vec::new();
+                self.initialized = false;
+            }
+            1 => {
+                self.velocity = state.buffers.into_iter().next().unwrap();
+                self.initialized = true;
+            }
+            n => {
+                return Err(format!(
+                    "gradient descent expects at most one state buffer, got {}",
+                    n
+                ))
+            }
         }
+        self.learning_rate_scale = state.learning_rate_scale;
+        Ok(())
     }
 }
 
-pub fn sgd_optimizer(
-    learning_rate: Option<f64>,
-    schedule: Option<fn(u64) -> f64>,
-) -> GradientDescentOptimizer {
+pub fn sgd_optimizer(learning_rate: impl LrSchedule + 'static) -> GradientDescentOptimizer {
     GradientDescentOptimizer {
-        learning_rate,
-        learning_rate_schedule: schedule,
+        learning_rate: Box::new(learning_rate),
+        learning_rate_scale: 1.0,
         momentum: 0.0,
-        is_nesterov: false,
-        current_weights: Vec::new(),
+        clipping: None,
         velocity: Vec::new(),
         initialized: false,
     }
 }
 
-fn get_learning_rate(lr: Option<f64>, schedule: Option<fn(u64) -> f64>, iteration: u64) -> f64 {
-    if schedule.is_some() {
-        return (schedule.unwrap())(iteration);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activation::ActivationFunctionKey;
+    use crate::error::ErrorFunctionKey;
+    use crate::network::{create_network, global_norm};
+    use crate::schedule::Constant;
+    use crate::utils::DataSetItem;
+
+    fn gradients() -> Vec<Matrix> {
+        vec![
+            Matrix::from(&vec![vec![3.0, -4.0]]),
+            Matrix::from(&vec![vec![0.0], vec![12.0]]),
+        ]
+    }
+
+    #[test]
+    fn clips_by_value() {
+        let clipped = clip_gradients(&gradients(), GradientClipKey::Value(2.0), 13.0);
+        assert_eq!(clipped[0].items, vec![2.0, -2.0]);
+        assert_eq!(clipped[1].items, vec![0.0, 2.0]);
     }
-    if lr.is_some() {
-        return lr.unwrap();
+
+    #[test]
+    fn clips_by_global_norm() {
+        let gradients = gradients();
+        let norm = global_norm(&gradients);
+        assert_eq!(norm, 13.0);
+
+        let clipped = clip_gradients(&gradients, GradientClipKey::GlobalNorm(6.5), norm);
+        assert_eq!(clipped[0].items, vec![1.5, -2.0]);
+        assert_eq!(clipped[1].items, vec![0.0, 6.0]);
+
+        let unchanged = clip_gradients(&gradients, GradientClipKey::GlobalNorm(20.0), norm);
+        assert_eq!(unchanged[1].items, gradients[1].items);
+    }
+
+    #[test]
+    fn optimizer_applies_clipping() {
+        let mut network = create_network(ErrorFunctionKey::MeanSquared);
+        network.add(ActivationFunctionKey::ReLu, 1);
+        network.initialize(1, Some(vec![vec![vec![1.0, 1.0]]]));
+
+        let result = network.compute_gradients(&[DataSetItem {
+            input: vec![10.0],
+            output: vec![100.0],
+        }]);
+        assert!(result.gradient_norm > 1.0);
+
+        let mut optimizer = sgd_optimizer(Constant(1.0));
+        optimizer.clipping = Some(GradientClipKey::GlobalNorm(1.0));
+        optimizer.do_update(&result, &mut network, 0);
+
+        let initial = Matrix::from(&vec![vec![1.0, 1.0]]);
+        let step = global_norm(&[network.layers[0].parameters().subtract(&initial)]);
+        assert!((step - 1.0).abs() < 1e-12, "step {}", step);
+    }
+
+    #[test]
+    fn rejects_invalid_clipping_limits() {
+        for clip in [
+            GradientClipKey::Value(-1.0),
+            GradientClipKey::Value(f64::NAN),
+            GradientClipKey::GlobalNorm(-1.0),
+            GradientClipKey::GlobalNorm(0.0),
+            GradientClipKey::GlobalNorm(f64::INFINITY),
+        ] {
+            assert!(!clip.is_valid(), "{:?}", clip);
+        }
+        assert!(GradientClipKey::Value(2.0).is_valid());
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid gradient clipping Value(-1.0)")]
+    fn optimizer_rejects_invalid_clipping() {
+        let mut network = create_network(ErrorFunctionKey::MeanSquared);
+        network.add(ActivationFunctionKey::ReLu, 1);
+        network.initialize(1, None);
+        let result = network.compute_gradients(&[DataSetItem {
+            input: vec![1.0],
+            output: vec![1.0],
+        }]);
+
+        let mut optimizer = sgd_optimizer(Constant(1.0));
+        optimizer.clipping = Some(GradientClipKey::Value(-1.0));
+        optimizer.do_update(&result, &mut network, 0);
     }
-    panic!("Invalid learning rate supplied")
 }
@@ -0,0 +1,337 @@
+use crate::conv::Window;
+use crate::layer::{ForwardPassResult, Layer, LayerKey, Shape};
+use crate::matrix::Matrix;
+use crate::random::SeededRng;
+
+/// How a pooling layer combines the values under its window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PoolKey {
+    Max,
+    Average,
+}
+
+/// Value of channel `c` at position `p` of `input`.
+fn value(input: &Matrix, shape: Shape, c: usize, p: usize) -> f64 {
+    input.get(c * shape.height * shape.width + p, 0)
+}
+
+/// Position in `field` holding the largest value; the first one on ties.
+fn max_position(input: &Matrix, shape: Shape, c: usize, field: &[usize]) -> usize {
+    let mut best = field[0];
+    for &p in &field[1..] {
+        if value(input, shape, c, p) > value(input, shape, c, best) {
+            best = p;
+        }
+    }
+    best
+}
+
+/// Pools every channel of `input` separately.
+pub fn pool(key: PoolKey, window: &Window, input: &Matrix, shape: Shape, output: Shape) -> Matrix {
+    let mut result = Vec::with_capacity(output.size());
+    for c in 0..shape.channels {
+        for oy in 0..output.height {
+            for ox in 0..output.width {
+                let field = window.field(oy, ox, shape);
+                result.push(match key {
+                    PoolKey::Max => value(input, shape, c, max_position(input, shape, c, &field)),
+                    PoolKey::Average => {
+                        let sum: f64 = field.iter().map(|&p| value(input, shape, c, p)).sum();
+                        sum / field.len() as f64
+                    }
+                });
+            }
+        }
+    }
+    Matrix::from(&result)
+}
+
+/// Routes `delta`, the gradient with respect to the pooled output, back to
+/// the input: the maximum of each window receives all of it, averages
+/// share it equally. Overlapping windows add up.
+pub fn pool_gradient(
+    key: PoolKey,
+    window: &Window,
+    delta: &Matrix,
+    input: &Matrix,
+    shape: Shape,
+    output: Shape,
+) -> Matrix {
+    let plane = shape.height * shape.width;
+    let mut result = vec![0.0; shape.size()];
+    for c in 0..shape.channels {
+        for oy in 0..output.height {
+            for ox in 0..output.width {
+                let field = window.field(oy, ox, shape);
+                let d = delta.get((c * output.height + oy) * output.width + ox, 0);
+                match key {
+                    PoolKey::Max => {
+                        result[c * plane + max_position(input, shape, c, &field)] += d;
+                    }
+                    PoolKey::Average => {
+                        for &p in &field {
+                            result[c * plane + p] += d / field.len() as f64;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Matrix::from(&result)
+}
+
+/// Mean of each channel, as a flat vector with one value per channel.
+pub fn global_average(input: &Matrix, shape: Shape) -> Matrix {
+    let plane = shape.height * shape.width;
+    let result: Vec<f64> = (0..shape.channels)
+        .map(|c| (0..plane).map(|p| value(input, shape, c, p)).sum::<f64>() / plane as f64)
+        .collect();
+    Matrix::from(&result)
+}
+
+pub fn global_average_gradient(delta: &Matrix, shape: Shape) -> Matrix {
+    let plane = shape.height * shape.width;
+    let result: Vec<f64> = (0..shape.size())
+        .map(|i| delta.get(i / plane, 0) / plane as f64)
+        .collect();
+    Matrix::from(&result)
+}
+
+/// Pools each channel over the window. Pooling windows should not be
+/// padded.
+pub struct Pool2d {
+    key: PoolKey,
+    window: Window,
+    input_shape: Shape,
+    output_shape: Shape,
+}
+
+impl Pool2d {
+    pub fn new(key: PoolKey, window: Window) -> Pool2d {
+        Pool2d {
+            key,
+            window,
+            input_shape: Shape::flat(0),
+            output_shape: Shape::flat(0),
+        }
+    }
+}
+
+impl Layer for Pool2d {
+    fn name(&self) -> String {
+        format!(
+            "{:?}Pool2d {}x{}/{}",
+            self.key, self.window.kernel, self.window.kernel, self.window.stride
+        )
+    }
+
+    /// Padding is not supported: windows that only cover padding would
+    /// have nothing to pool.
+    fn output_shape(&self, input: Shape) -> Result<Shape, String> {
+        if self.window.padding != 0 {
+            return Err(format!(
+                "pooling does not support padding, got {}",
+                self.window.padding
+            ));
+        }
+        self.window
+            .output_shape(input, input.channels)
+            .ok_or_else(|| format!("{:?} does not fit input {}", self.window, input))
+    }
+
+    fn initialize(&mut self, input: Shape, _rng: &mut SeededRng) {
+        self.input_shape = input;
+        self.output_shape = self
+            .output_shape(input)
+            .unwrap_or_else(|message| panic!("{}", message));
+    }
+
+    fn forward(&self, input: &Matrix, _rng: Option<&mut SeededRng>) -> ForwardPassResult {
+        ForwardPassResult {
+            activated: pool(
+                self.key,
+                &self.window,
+                input,
+                self.input_shape,
+                self.output_shape,
+            ),
+            cache: Matrix::zeros(0, 0),
+        }
+    }
+
+    fn backward(
+        &self,
+        input: &Matrix,
+        _result: &ForwardPassResult,
+        output_gradient: &Matrix,
+    ) -> Matrix {
+        pool_gradient(
+            self.key,
+            &self.window,
+            output_gradient,
+            input,
+            self.input_shape,
+            self.output_shape,
+        )
+    }
+
+    fn key(&self) -> Option<LayerKey> {
+        Some(LayerKey::Pool2d(self.key, self.window))
+    }
+}
+
+/// Averages each channel down to a single value, giving a flat vector with
+/// one value per channel.
+pub struct GlobalAvgPool {
+    input_shape: Shape,
+}
+
+impl GlobalAvgPool {
+    pub fn new() -> GlobalAvgPool {
+        GlobalAvgPool {
+            input_shape: Shape::flat(0),
+        }
+    }
+}
+
+impl Default for GlobalAvgPool {
+    fn default() -> GlobalAvgPool {
+        GlobalAvgPool::new()
+    }
+}
+
+impl Layer for GlobalAvgPool {
+    fn name(&self) -> String {
+        "GlobalAvgPool".to_string()
+    }
+
+    fn output_shape(&self, input: Shape) -> Result<Shape, String> {
+        Ok(Shape::flat(input.channels))
+    }
+
+    fn initialize(&mut self, input: Shape, _rng: &mut SeededRng) {
+        self.input_shape = input;
+    }
+
+    fn forward(&self, input: &Matrix, _rng: Option<&mut SeededRng>) -> ForwardPassResult {
+        ForwardPassResult {
+            activated: global_average(input, self.input_shape),
+            cache: Matrix::zeros(0, 0),
+        }
+    }
+
+    fn backward(
+        &self,
+        _input: &Matrix,
+        _result: &ForwardPassResult,
+        output_gradient: &Matrix,
+    ) -> Matrix {
+        global_average_gradient(output_gradient, self.input_shape)
+    }
+
+    fn key(&self) -> Option<LayerKey> {
+        Some(LayerKey::GlobalAvgPool)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape(channels: usize, height: usize, width: usize) -> Shape {
+        Shape {
+            channels,
+            height,
+            width,
+        }
+    }
+
+    fn window(kernel: usize, stride: usize) -> Window {
+        Window {
+            kernel,
+            stride,
+            padding: 0,
+        }
+    }
+
+    fn image(values: Vec<f64>) -> Matrix {
+        Matrix::from(&values)
+    }
+
+    fn grid() -> Matrix {
+        image(vec![
+            1.0, 5.0, 2.0, 0.0, //
+            3.0, 4.0, 8.0, 1.0, //
+            0.0, 2.0, 7.0, 7.0, //
+            6.0, 1.0, 3.0, 2.0,
+        ])
+    }
+
+    #[test]
+    fn pools_windows() {
+        let input_shape = shape(1, 4, 4);
+        let w = window(2, 2);
+        let output = w.output_shape(input_shape, 1).unwrap();
+        assert_eq!(
+            pool(PoolKey::Max, &w, &grid(), input_shape, output).items,
+            vec![5.0, 8.0, 6.0, 7.0]
+        );
+        assert_eq!(
+            pool(PoolKey::Average, &w, &grid(), input_shape, output).items,
+            vec![3.25, 2.75, 2.25, 4.75]
+        );
+    }
+
+    #[test]
+    fn max_pool_routes_gradient_to_maximum() {
+        let input_shape = shape(1, 4, 4);
+        let w = window(2, 2);
+        let output = w.output_shape(input_shape, 1).unwrap();
+        let delta = Matrix::from(&vec![1.0, 2.0, 3.0, 4.0]);
+        let gradient = pool_gradient(PoolKey::Max, &w, &delta, &grid(), input_shape, output);
+        assert_eq!(
+            gradient.items,
+            vec![
+                0.0, 1.0, 0.0, 0.0, //
+                0.0, 0.0, 2.0, 0.0, //
+                0.0, 0.0, 4.0, 0.0, //
+                3.0, 0.0, 0.0, 0.0,
+            ]
+        );
+    }
+
+    #[test]
+    fn overlapping_average_windows_add_up() {
+        let input_shape = shape(1, 2, 3);
+        let w = window(2, 1);
+        let output = w.output_shape(input_shape, 1).unwrap();
+        let delta = Matrix::from(&vec![4.0, 8.0]);
+        let input = image(vec![0.0; 6]);
+        let gradient = pool_gradient(PoolKey::Average, &w, &delta, &input, input_shape, output);
+        assert_eq!(gradient.items, vec![1.0, 3.0, 2.0, 1.0, 3.0, 2.0]);
+    }
+
+    #[test]
+    fn rejects_padding() {
+        let padded = Window {
+            padding: 1,
+            ..window(2, 2)
+        };
+        let layer = Pool2d::new(PoolKey::Max, padded);
+        assert_eq!(
+            layer.output_shape(shape(1, 4, 4)),
+            Err("pooling does not support padding, got 1".to_string())
+        );
+    }
+
+    #[test]
+    fn global_average_per_channel() {
+        let input_shape = shape(2, 1, 2);
+        let input = image(vec![1.0, 3.0, -2.0, 6.0]);
+        assert_eq!(global_average(&input, input_shape).items, vec![2.0, 2.0]);
+        assert_eq!(
+            global_average_gradient(&Matrix::from(&vec![1.0, -4.0]), input_shape).items,
+            vec![0.5, 0.5, -2.0, -2.0]
+        );
+    }
+}
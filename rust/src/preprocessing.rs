@@ -0,0 +1,636 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::utils::{one_hot_encode, DataSetItem};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ScalerKey {
+    MinMax,
+    Standard,
+    MaxAbs,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColumnKind {
+    Numeric,
+    OneHot,
+    Ordinal,
+    Skip,
+}
+
+#[derive(Debug)]
+pub enum PreprocessError {
+    Io(io::Error),
+    Format(String),
+    ColumnCount { expected: usize, found: usize },
+    InvalidNumber { column: usize, value: String },
+    UnknownCategory { column: usize, value: String },
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PreprocessError::Io(e) => write!(f, "{}", e),
+            PreprocessError::Format(message) => write!(f, "invalid saved state: {}", message),
+            PreprocessError::ColumnCount { expected, found } => {
+                write!(f, "expected {} columns, found {}", expected, found)
+            }
+            PreprocessError::InvalidNumber { column, value } => {
+                write!(
+                    f,
+                    "column {}: could not parse {:?} as a number",
+                    column, value
+                )
+            }
+            PreprocessError::UnknownCategory { column, value } => {
+                write!(f, "column {}: unknown category {:?}", column, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PreprocessError {}
+
+impl From<io::Error> for PreprocessError {
+    fn from(e: io::Error) -> PreprocessError {
+        PreprocessError::Io(e)
+    }
+}
+
+/// Per-feature affine scaling, `(x - offset) / scale`, for numeric inputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scaler {
+    pub key: ScalerKey,
+    pub offset: Vec<f64>,
+    pub scale: Vec<f64>,
+}
+
+impl Scaler {
+    pub fn fit(key: ScalerKey, data: &[DataSetItem]) -> Scaler {
+        let width = data.first().map(|item| item.input.len()).unwrap_or(0);
+        let mut offset = Vec::with_capacity(width);
+        let mut scale = Vec::with_capacity(width);
+        for j in 0..width {
+            let values: Vec<f64> = data.iter().map(|item| item.input[j]).collect();
+            let (o, s) = fit_column(key, &values);
+            offset.push(o);
+            scale.push(s);
+        }
+        Scaler { key, offset, scale }
+    }
+
+    pub fn transform(&self, input: &[f64]) -> Vec<f64> {
+        input
+            .iter()
+            .enumerate()
+            .map(|(j, x)| (x - self.offset[j]) / self.scale[j])
+            .collect()
+    }
+
+    pub fn inverse_transform(&self, input: &[f64]) -> Vec<f64> {
+        input
+            .iter()
+            .enumerate()
+            .map(|(j, x)| x * self.scale[j] + self.offset[j])
+            .collect()
+    }
+
+    pub fn transform_dataset(&self, data: &mut [DataSetItem]) {
+        for item in data {
+            item.input = self.transform(&item.input);
+        }
+    }
+
+    pub fn save<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "scaler\t{}", scaler_name(self.key))?;
+        writeln!(writer, "{}", join_numbers(&self.offset))?;
+        writeln!(writer, "{}", join_numbers(&self.scale))
+    }
+
+    pub fn load<R: BufRead>(reader: &mut R) -> Result<Scaler, PreprocessError> {
+        let header = read_line(reader)?;
+        let key = match header.split('\t').collect::<Vec<&str>>()[..] {
+            ["scaler", name] => parse_scaler_name(name)?,
+            _ => return Err(PreprocessError::Format(format!("bad header {:?}", header))),
+        };
+        let offset = parse_numbers(&read_line(reader)?)?;
+        let scale = parse_numbers(&read_line(reader)?)?;
+        if offset.len() != scale.len() {
+            return Err(PreprocessError::Format("length mismatch".to_string()));
+        }
+        Ok(Scaler { key, offset, scale })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnTransform {
+    Numeric { offset: f64, scale: f64 },
+    OneHot(Vec<String>),
+    Ordinal(Vec<String>),
+    Skip,
+}
+
+pub struct PreprocessorOptions {
+    pub scaler: ScalerKey,
+    /// Encoding used for columns detected as categorical.
+    pub categorical_encoding: ColumnKind,
+    /// Numeric columns with fewer distinct values than this are treated as
+    /// categorical. Defaults to 0, so only columns with non-numeric values
+    /// are.
+    pub categorical_threshold: usize,
+    pub overrides: Vec<(usize, ColumnKind)>,
+}
+
+impl Default for PreprocessorOptions {
+    fn default() -> PreprocessorOptions {
+        PreprocessorOptions {
+            scaler: ScalerKey::MaxAbs,
+            categorical_encoding: ColumnKind::OneHot,
+            categorical_threshold: 0,
+            overrides: Vec::new(),
+        }
+    }
+}
+
+/// Turns raw string records, such as split CSV lines, into feature vectors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preprocessor {
+    pub scaler: ScalerKey,
+    pub columns: Vec<ColumnTransform>,
+}
+
+impl Preprocessor {
+    pub fn fit<S: AsRef<str>>(
+        rows: &[Vec<S>],
+        options: &PreprocessorOptions,
+    ) -> Result<Preprocessor, PreprocessError> {
+        let width = rows.first().map(|row| row.len()).unwrap_or(0);
+        check_width(rows, width)?;
+
+        let mut columns = Vec::with_capacity(width);
+        for j in 0..width {
+            let values: Vec<&str> = rows.iter().map(|row| row[j].as_ref().trim()).collect();
+            let distinct = distinct_values(&values);
+            let is_numeric = values.iter().all(|v| v.parse::<f64>().is_ok());
+
+            let kind = match options.overrides.iter().find(|(c, _)| *c == j) {
+                Some((_, kind)) => *kind,
+                None if !is_numeric || distinct.len() < options.categorical_threshold => {
+                    options.categorical_encoding
+                }
+                None => ColumnKind::Numeric,
+            };
+
+            columns.push(match kind {
+                ColumnKind::Numeric => {
+                    let numbers = parse_column(j, &values)?;
+                    let (offset, scale) = fit_column(options.scaler, &numbers);
+                    ColumnTransform::Numeric { offset, scale }
+                }
+                ColumnKind::OneHot => ColumnTransform::OneHot(distinct),
+                ColumnKind::Ordinal => ColumnTransform::Ordinal(distinct),
+                ColumnKind::Skip => ColumnTransform::Skip,
+            });
+        }
+        Ok(Preprocessor {
+            scaler: options.scaler,
+            columns,
+        })
+    }
+
+    pub fn output_len(&self) -> usize {
+        self.columns
+            .iter()
+            .map(|c| match c {
+                ColumnTransform::Numeric { .. } | ColumnTransform::Ordinal(_) => 1,
+                ColumnTransform::OneHot(categories) => categories.len(),
+                ColumnTransform::Skip => 0,
+            })
+            .sum()
+    }
+
+    pub fn transform<S: AsRef<str>>(&self, row: &[S]) -> Result<Vec<f64>, PreprocessError> {
+        if row.len() != self.columns.len() {
+            return Err(PreprocessError::ColumnCount {
+                expected: self.columns.len(),
+                found: row.len(),
+            });
+        }
+        let mut result = Vec::with_capacity(self.output_len());
+        for (j, (column, value)) in self.columns.iter().zip(row).enumerate() {
+            let value = value.as_ref().trim();
+            match column {
+                ColumnTransform::Numeric { offset, scale } => {
+                    let x = parse_value(j, value)?;
+                    result.push((x - offset) / scale);
+                }
+                ColumnTransform::OneHot(categories) => {
+                    let index = category_index(j, categories, value)?;
+                    result.extend(one_hot_encode(index as f64, categories.len()));
+                }
+                ColumnTransform::Ordinal(categories) => {
+                    result.push(category_index(j, categories, value)? as f64);
+                }
+                ColumnTransform::Skip => {}
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn transform_rows<S: AsRef<str>>(
+        &self,
+        rows: &[Vec<S>],
+    ) -> Result<Vec<Vec<f64>>, PreprocessError> {
+        rows.iter().map(|row| self.transform(row)).collect()
+    }
+
+    pub fn save<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "preprocessor\t{}\t{}",
+            scaler_name(self.scaler),
+            self.columns.len()
+        )?;
+        for column in &self.columns {
+            match column {
+                ColumnTransform::Numeric { offset, scale } => {
+                    writeln!(writer, "numeric\t{}\t{}", offset, scale)?
+                }
+                ColumnTransform::OneHot(categories) => {
+                    writeln!(writer, "onehot{}", join_categories(categories))?
+                }
+                ColumnTransform::Ordinal(categories) => {
+                    writeln!(writer, "ordinal{}", join_categories(categories))?
+                }
+                ColumnTransform::Skip => writeln!(writer, "skip")?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load<R: BufRead>(reader: &mut R) -> Result<Preprocessor, PreprocessError> {
+        let header = read_line(reader)?;
+        let (scaler, count) = match header.split('\t').collect::<Vec<&str>>()[..] {
+            ["preprocessor", name, count] => (
+                parse_scaler_name(name)?,
+                count
+                    .parse::<usize>()
+                    .map_err(|_| PreprocessError::Format(format!("bad count {:?}", count)))?,
+            ),
+            _ => return Err(PreprocessError::Format(format!("bad header {:?}", header))),
+        };
+
+        let mut columns = Vec::with_capacity(count);
+        for _ in 0..count {
+            let line = read_line(reader)?;
+            let mut fields = line.split('\t');
+            let column = match fields.next() {
+                Some("numeric") => {
+                    let numbers = parse_numbers(&fields.collect::<Vec<&str>>().join(" "))?;
+                    if numbers.len() != 2 {
+                        return Err(PreprocessError::Format(format!("bad column {:?}", line)));
+                    }
+                    ColumnTransform::Numeric {
+                        offset: numbers[0],
+                        scale: numbers[1],
+                    }
+                }
+                Some("onehot") => ColumnTransform::OneHot(fields.map(unescape).collect()),
+                Some("ordinal") => ColumnTransform::Ordinal(fields.map(unescape).collect()),
+                Some("skip") => ColumnTransform::Skip,
+                _ => return Err(PreprocessError::Format(format!("bad column {:?}", line))),
+            };
+            columns.push(column);
+        }
+        Ok(Preprocessor { scaler, columns })
+    }
+}
+
+fn fit_column(key: ScalerKey, values: &[f64]) -> (f64, f64) {
+    if values.is_empty() {
+        return (0.0, 1.0);
+    }
+    let (offset, scale) = match key {
+        ScalerKey::MinMax => {
+            let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+            let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+            (min, max - min)
+        }
+        ScalerKey::Standard => {
+            let n = values.len() as f64;
+            let mean = values.iter().sum::<f64>() / n;
+            let variance = values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+            (mean, variance.sqrt())
+        }
+        ScalerKey::MaxAbs => (0.0, values.iter().fold(0.0, |m: f64, x| m.max(x.abs()))),
+    };
+    // Constant columns are only shifted, never divided by zero.
+    if scale == 0.0 {
+        (offset, 1.0)
+    } else {
+        (offset, scale)
+    }
+}
+
+fn check_width<S: AsRef<str>>(rows: &[Vec<S>], width: usize) -> Result<(), PreprocessError> {
+    match rows.iter().find(|row| row.len() != width) {
+        Some(row) => Err(PreprocessError::ColumnCount {
+            expected: width,
+            found: row.len(),
+        }),
+        None => Ok(()),
+    }
+}
+
+fn distinct_values(values: &[&str]) -> Vec<String> {
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut result = Vec::new();
+    for value in values {
+        if seen.insert(value) {
+            result.push(value.to_string());
+        }
+    }
+    result
+}
+
+fn category_index(
+    column: usize,
+    categories: &[String],
+    value: &str,
+) -> Result<usize, PreprocessError> {
+    categories
+        .iter()
+        .position(|c| c == value)
+        .ok_or_else(|| PreprocessError::UnknownCategory {
+            column,
+            value: value.to_string(),
+        })
+}
+
+fn parse_value(column: usize, value: &str) -> Result<f64, PreprocessError> {
+    value
+        .parse::<f64>()
+        .map_err(|_| PreprocessError::InvalidNumber {
+            column,
+            value: value.to_string(),
+        })
+}
+
+fn parse_column(column: usize, values: &[&str]) -> Result<Vec<f64>, PreprocessError> {
+    values.iter().map(|v| parse_value(column, v)).collect()
+}
+
+fn scaler_name(key: ScalerKey) -> &'static str {
+    match key {
+        ScalerKey::MinMax => "minmax",
+        ScalerKey::Standard => "standard",
+        ScalerKey::MaxAbs => "maxabs",
+    }
+}
+
+fn parse_scaler_name(name: &str) -> Result<ScalerKey, PreprocessError> {
+    match name {
+        "minmax" => Ok(ScalerKey::MinMax),
+        "standard" => Ok(ScalerKey::Standard),
+        "maxabs" => Ok(ScalerKey::MaxAbs),
+        _ => Err(PreprocessError::Format(format!(
+            "unknown scaler {:?}",
+            name
+        ))),
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, PreprocessError> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(PreprocessError::Format(
+            "unexpected end of input".to_string(),
+        ));
+    }
+    Ok(line.trim_end_matches(['\n', '\r']).to_string())
+}
+
+fn join_numbers(values: &[f64]) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn parse_numbers(line: &str) -> Result<Vec<f64>, PreprocessError> {
+    line.split_whitespace()
+        .map(|v| {
+            v.parse::<f64>()
+                .map_err(|_| PreprocessError::Format(format!("bad number {:?}", v)))
+        })
+        .collect()
+}
+
+fn join_categories(categories: &[String]) -> String {
+    categories
+        .iter()
+        .map(|c| format!("\t{}", escape(c)))
+        .collect()
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(inputs: &[[f64; 2]]) -> Vec<DataSetItem> {
+        inputs
+            .iter()
+            .map(|input| DataSetItem {
+                input: input.to_vec(),
+                output: vec![0.0],
+            })
+            .collect()
+    }
+
+    fn rows(data: &[&str]) -> Vec<Vec<String>> {
+        data.iter()
+            .map(|line| line.split(',').map(|v| v.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn scalers_fit_and_transform() {
+        let data = items(&[[1.0, -4.0], [3.0, 2.0], [5.0, 2.0]]);
+
+        let min_max = Scaler::fit(ScalerKey::MinMax, &data);
+        assert_eq!(min_max.transform(&[3.0, -1.0]), vec![0.5, 0.5]);
+
+        let max_abs = Scaler::fit(ScalerKey::MaxAbs, &data);
+        assert_eq!(max_abs.transform(&[5.0, 2.0]), vec![1.0, 0.5]);
+
+        let standard = Scaler::fit(ScalerKey::Standard, &data);
+        let mut scaled = data.clone();
+        standard.transform_dataset(&mut scaled);
+        for j in 0..2 {
+            let column: Vec<f64> = scaled.iter().map(|item| item.input[j]).collect();
+            let mean = column.iter().sum::<f64>() / 3.0;
+            let variance = column.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / 3.0;
+            assert!(mean.abs() < 1e-12);
+            assert!((variance - 1.0).abs() < 1e-12);
+        }
+        let restored = standard.inverse_transform(&scaled[1].input);
+        assert!((restored[0] - 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn constant_columns_are_not_divided_by_zero() {
+        let scaler = Scaler::fit(ScalerKey::MinMax, &items(&[[2.0, 1.0], [2.0, 3.0]]));
+        assert_eq!(scaler.transform(&[2.0, 3.0]), vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn scaler_round_trips_through_text() {
+        let scaler = Scaler::fit(ScalerKey::Standard, &items(&[[0.1, 7.0], [0.3, -2.5]]));
+        let mut buffer = Vec::new();
+        scaler.save(&mut buffer).unwrap();
+        let loaded = Scaler::load(&mut buffer.as_slice()).unwrap();
+        assert_eq!(loaded, scaler);
+    }
+
+    #[test]
+    fn detects_categorical_columns() {
+        let data = rows(&["1,red,10", "2,blue,20", "3,red,40", "4,green,30"]);
+        let options = PreprocessorOptions {
+            categorical_threshold: 4,
+            ..PreprocessorOptions::default()
+        };
+        let preprocessor = Preprocessor::fit(&data, &options).unwrap();
+
+        assert_eq!(
+            preprocessor.columns[0],
+            ColumnTransform::Numeric {
+                offset: 0.0,
+                scale: 4.0
+            }
+        );
+        assert_eq!(
+            preprocessor.columns[1],
+            ColumnTransform::OneHot(vec![
+                "red".to_string(),
+                "blue".to_string(),
+                "green".to_string()
+            ])
+        );
+        assert_eq!(preprocessor.output_len(), 5);
+        assert_eq!(
+            preprocessor.transform(&["2", "green", "20"]).unwrap(),
+            vec![0.5, 0.0, 0.0, 1.0, 0.5]
+        );
+    }
+
+    #[test]
+    fn numeric_columns_stay_numeric_by_default() {
+        let data = rows(&[
+            "1,0.5", "2,0.5", "3,1.5", "1,2.5", "2,0.5", "3,1.5", "1,2.5", "2,0.5", "3,1.5",
+            "4,2.5",
+        ]);
+        let preprocessor = Preprocessor::fit(&data, &PreprocessorOptions::default()).unwrap();
+        assert_eq!(
+            preprocessor.columns,
+            vec![
+                ColumnTransform::Numeric {
+                    offset: 0.0,
+                    scale: 4.0
+                },
+                ColumnTransform::Numeric {
+                    offset: 0.0,
+                    scale: 2.5
+                },
+            ]
+        );
+        assert_eq!(preprocessor.output_len(), 2);
+    }
+
+    #[test]
+    fn overrides_and_ordinal_encoding() {
+        let data = rows(&["a,1", "b,2", "a,3"]);
+        let options = PreprocessorOptions {
+            categorical_encoding: ColumnKind::Ordinal,
+            overrides: vec![(1, ColumnKind::Skip)],
+            ..PreprocessorOptions::default()
+        };
+        let preprocessor = Preprocessor::fit(&data, &options).unwrap();
+        assert_eq!(
+            preprocessor.transform_rows(&data).unwrap(),
+            vec![vec![0.0], vec![1.0], vec![0.0]]
+        );
+    }
+
+    #[test]
+    fn transform_reports_bad_input() {
+        let data = rows(&["a,1", "b,2"]);
+        let options = PreprocessorOptions {
+            overrides: vec![(1, ColumnKind::Numeric)],
+            ..PreprocessorOptions::default()
+        };
+        let preprocessor = Preprocessor::fit(&data, &options).unwrap();
+        assert!(matches!(
+            preprocessor.transform(&["c", "1"]),
+            Err(PreprocessError::UnknownCategory { column: 0, .. })
+        ));
+        assert!(matches!(
+            preprocessor.transform(&["a", "x"]),
+            Err(PreprocessError::InvalidNumber { column: 1, .. })
+        ));
+        assert!(matches!(
+            preprocessor.transform(&["a"]),
+            Err(PreprocessError::ColumnCount {
+                expected: 2,
+                found: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn preprocessor_round_trips_through_text() {
+        let data = rows(&["x\ty,1.5,p", "back\\slash,2.5,q", "x\ty,3.5,p"]);
+        let options = PreprocessorOptions {
+            scaler: ScalerKey::MinMax,
+            categorical_threshold: 0,
+            overrides: vec![(2, ColumnKind::Ordinal)],
+            ..PreprocessorOptions::default()
+        };
+        let preprocessor = Preprocessor::fit(&data, &options).unwrap();
+
+        let mut buffer = Vec::new();
+        preprocessor.save(&mut buffer).unwrap();
+        let loaded = Preprocessor::load(&mut buffer.as_slice()).unwrap();
+        assert_eq!(loaded, preprocessor);
+        assert_eq!(
+            loaded.transform(&["back\\slash", "3.5", "p"]).unwrap(),
+            vec![0.0, 1.0, 1.0, 0.0]
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use rand::{Error, Rng, RngCore};
+
+/// xoshiro256** generator. Its whole state is four words, so it can be
+/// seeded, copied and stored alongside a model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeededRng {
+    state: [u64; 4],
+}
+
+impl SeededRng {
+    pub fn from_seed(seed: u64) -> SeededRng {
+        let mut seed = seed;
+        let mut state = [0_u64; 4];
+        for word in state.iter_mut() {
+            *word = splitmix64(&mut seed);
+        }
+        SeededRng { state }
+    }
+
+    pub fn from_entropy() -> SeededRng {
+        SeededRng::from_seed(rand::thread_rng().gen())
+    }
+
+    pub fn from_state(state: [u64; 4]) -> SeededRng {
+        if state == [0; 4] {
+            panic!("xoshiro state can not be all zeros");
+        }
+        SeededRng { state }
+    }
+
+    pub fn state(&self) -> [u64; 4] {
+        self.state
+    }
+}
+
+impl RngCore for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+
+        result
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Standard normal sample using the Box-Muller transform.
+pub fn sample_normal(rng: &mut impl Rng) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+fn splitmix64(seed: &mut u64) -> u64 {
+    *seed = seed.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *seed;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = SeededRng::from_seed(42);
+        let mut b = SeededRng::from_seed(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(
+            SeededRng::from_seed(1).next_u64(),
+            SeededRng::from_seed(2).next_u64()
+        );
+    }
+
+    #[test]
+    fn restores_from_state() {
+        let mut rng = SeededRng::from_seed(7);
+        rng.gen_range(0..10);
+        let mut restored = SeededRng::from_state(rng.state());
+        for _ in 0..10 {
+            assert_eq!(rng.gen::<f64>(), restored.gen::<f64>());
+        }
+    }
+
+    #[test]
+    fn sample_normal_has_unit_variance() {
+        let mut rng = SeededRng::from_seed(5);
+        let samples: Vec<f64> = (0..20000).map(|_| sample_normal(&mut rng)).collect();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let variance =
+            samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+        assert!(mean.abs() < 0.03);
+        assert!((variance - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn fill_bytes_handles_partial_chunks() {
+        let mut bytes = [0_u8; 11];
+        SeededRng::from_seed(3).fill_bytes(&mut bytes);
+        assert!(bytes.iter().any(|b| *b != 0));
+    }
+}
@@ -0,0 +1,89 @@
+use crate::matrix::Matrix;
+
+/// Weight penalties added to the loss. Bias weights (column 0) are never
+/// penalized. L2 uses `0.5 * lambda * sum(w^2)` so its gradient is
+/// `lambda * w`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegularizerKey {
+    L1(f64),
+    L2(f64),
+    L1L2 { l1: f64, l2: f64 },
+}
+
+impl RegularizerKey {
+    fn strengths(self) -> (f64, f64) {
+        match self {
+            RegularizerKey::L1(l1) => (l1, 0.0),
+            RegularizerKey::L2(l2) => (0.0, l2),
+            RegularizerKey::L1L2 { l1, l2 } => (l1, l2),
+        }
+    }
+
+    pub fn is_valid(self) -> bool {
+        let (l1, l2) = self.strengths();
+        l1 >= 0.0 && l2 >= 0.0
+    }
+
+    pub fn penalty(self, weights: &Matrix) -> f64 {
+        let (l1, l2) = self.strengths();
+        weights
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| index % weights.cols != 0)
+            .map(|(_, w)| l1 * w.abs() + 0.5 * l2 * w * w)
+            .sum()
+    }
+
+    pub fn gradient(self, weights: &Matrix) -> Matrix {
+        let (l1, l2) = self.strengths();
+        weights.map(&|w, _, j| {
+            if j == 0 {
+                0.0
+            } else {
+                let sign = if w > 0.0 {
+                    1.0
+                } else if w < 0.0 {
+                    -1.0
+                } else {
+                    0.0
+                };
+                l1 * sign + l2 * w
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weights() -> Matrix {
+        Matrix::from(&vec![vec![5.0, 1.0, -2.0], vec![-5.0, 0.0, 3.0]])
+    }
+
+    #[test]
+    fn skips_bias_column() {
+        let l1 = RegularizerKey::L1(0.5);
+        assert_eq!(l1.penalty(&weights()), 0.5 * 6.0);
+        assert_eq!(
+            l1.gradient(&weights()).items,
+            vec![0.0, 0.5, -0.5, 0.0, 0.0, 0.5]
+        );
+
+        let l2 = RegularizerKey::L2(2.0);
+        assert_eq!(l2.penalty(&weights()), 14.0);
+        assert_eq!(
+            l2.gradient(&weights()).items,
+            vec![0.0, 2.0, -4.0, 0.0, 0.0, 6.0]
+        );
+    }
+
+    #[test]
+    fn combines_penalties() {
+        let both = RegularizerKey::L1L2 { l1: 0.5, l2: 2.0 };
+        assert_eq!(both.penalty(&weights()), 17.0);
+        assert!(both.is_valid());
+        assert!(!RegularizerKey::L2(-1.0).is_valid());
+    }
+}
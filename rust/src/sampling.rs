@@ -0,0 +1,219 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::utils::DataSetItem;
+use crate::validation::class_of;
+
+/// Duplicates random items of the smaller classes until each has at least
+/// `ratio` times as many items as the largest class.
+pub fn random_oversample(data: &[DataSetItem], ratio: f64, rng: &mut impl Rng) -> Vec<DataSetItem> {
+    resample_minorities(data, ratio, rng, |class_items, needed, rng| {
+        (0..needed)
+            .map(|_| (*class_items.choose(rng).unwrap()).clone())
+            .collect()
+    })
+}
+
+/// Drops random items of the larger classes until none has more than
+/// `1 / ratio` times as many items as the smallest class.
+pub fn random_undersample(
+    data: &[DataSetItem],
+    ratio: f64,
+    rng: &mut impl Rng,
+) -> Vec<DataSetItem> {
+    check_ratio(ratio);
+    let classes = group_by_class(data);
+    let smallest = classes
+        .iter()
+        .map(|c| c.len())
+        .filter(|&n| n > 0)
+        .min()
+        .unwrap_or(0);
+    let limit = (smallest as f64 / ratio).round() as usize;
+
+    let mut result = Vec::new();
+    for class_items in classes {
+        if class_items.len() > limit {
+            result.extend(
+                class_items
+                    .choose_multiple(rng, limit)
+                    .map(|item| (*item).clone()),
+            );
+        } else {
+            result.extend(class_items.into_iter().cloned());
+        }
+    }
+    result.shuffle(rng);
+    result
+}
+
+/// SMOTE: synthesizes new items for the smaller classes by interpolating
+/// between an item and one of its `k` nearest neighbours of the same class.
+pub fn smote(data: &[DataSetItem], ratio: f64, k: usize, rng: &mut impl Rng) -> Vec<DataSetItem> {
+    if k == 0 {
+        panic!("SMOTE needs at least one neighbour");
+    }
+    resample_minorities(data, ratio, rng, |class_items, needed, rng| {
+        let neighbours: Vec<Vec<usize>> = (0..class_items.len())
+            .map(|i| nearest_neighbours(class_items, i, k))
+            .collect();
+
+        let mut synthetic = Vec::with_capacity(needed);
+        for n in 0..needed {
+            let i = n % class_items.len();
+            let base = class_items[i];
+            let neighbour = match neighbours[i].choose(rng) {
+                Some(&j) => class_items[j],
+                None => base,
+            };
+            let gap: f64 = rng.gen();
+            synthetic.push(DataSetItem {
+                input: base
+                    .input
+                    .iter()
+                    .zip(&neighbour.input)
+                    .map(|(a, b)| a + gap * (b - a))
+                    .collect(),
+                output: base.output.clone(),
+            });
+        }
+        synthetic
+    })
+}
+
+fn resample_minorities<R, F>(
+    data: &[DataSetItem],
+    ratio: f64,
+    rng: &mut R,
+    mut generate: F,
+) -> Vec<DataSetItem>
+where
+    R: Rng,
+    F: FnMut(&[&DataSetItem], usize, &mut R) -> Vec<DataSetItem>,
+{
+    check_ratio(ratio);
+    let classes = group_by_class(data);
+    let largest = classes.iter().map(|c| c.len()).max().unwrap_or(0);
+    let target = (largest as f64 * ratio).round() as usize;
+
+    let mut result: Vec<DataSetItem> = data.to_vec();
+    for class_items in &classes {
+        if !class_items.is_empty() && class_items.len() < target {
+            result.extend(generate(class_items, target - class_items.len(), rng));
+        }
+    }
+    result.shuffle(rng);
+    result
+}
+
+fn check_ratio(ratio: f64) {
+    if ratio <= 0.0 || ratio > 1.0 {
+        panic!("Class ratio must be in (0, 1], got {}", ratio);
+    }
+}
+
+fn group_by_class(data: &[DataSetItem]) -> Vec<Vec<&DataSetItem>> {
+    let mut classes: Vec<Vec<&DataSetItem>> = Vec::new();
+    for item in data {
+        let class = class_of(item);
+        if classes.len() <= class {
+            classes.resize(class + 1, Vec::new());
+        }
+        classes[class].push(item);
+    }
+    classes
+}
+
+fn nearest_neighbours(items: &[&DataSetItem], index: usize, k: usize) -> Vec<usize> {
+    let mut distances: Vec<(usize, f64)> = items
+        .iter()
+        .enumerate()
+        .filter(|(j, _)| *j != index)
+        .map(|(j, other)| (j, squared_distance(&items[index].input, &other.input)))
+        .collect();
+    distances.sort_by(|a, b| a.1.total_cmp(&b.1));
+    distances.into_iter().take(k).map(|(j, _)| j).collect()
+}
+
+fn squared_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| (x - y).powi(2)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::SeededRng;
+
+    fn dataset(positives: usize, negatives: usize) -> Vec<DataSetItem> {
+        (0..positives + negatives)
+            .map(|i| {
+                let positive = i < positives;
+                DataSetItem {
+                    input: if positive {
+                        vec![i as f64, 10.0]
+                    } else {
+                        vec![i as f64, -10.0]
+                    },
+                    output: vec![if positive { 1.0 } else { 0.0 }],
+                }
+            })
+            .collect()
+    }
+
+    fn counts(data: &[DataSetItem]) -> (usize, usize) {
+        let positives = data.iter().filter(|item| class_of(item) == 1).count();
+        (positives, data.len() - positives)
+    }
+
+    #[test]
+    fn oversampling_reaches_ratio() {
+        let data = dataset(5, 40);
+        let result = random_oversample(&data, 0.5, &mut SeededRng::from_seed(0));
+        assert_eq!(counts(&result), (20, 40));
+        assert!(result
+            .iter()
+            .all(|item| data.iter().any(|d| d.input == item.input)));
+    }
+
+    #[test]
+    fn undersampling_reaches_ratio() {
+        let data = dataset(5, 40);
+        let result = random_undersample(&data, 0.5, &mut SeededRng::from_seed(0));
+        assert_eq!(counts(&result), (5, 10));
+
+        let mut ids: Vec<f64> = result.iter().map(|item| item.input[0]).collect();
+        ids.sort_by(|a, b| a.total_cmp(b));
+        ids.dedup();
+        assert_eq!(ids.len(), 15);
+    }
+
+    #[test]
+    fn smote_interpolates_within_class() {
+        let data = dataset(6, 30);
+        let result = smote(&data, 1.0, 3, &mut SeededRng::from_seed(1));
+        assert_eq!(counts(&result), (30, 30));
+        for item in result.iter().filter(|item| class_of(item) == 1) {
+            assert_eq!(item.input[1], 10.0);
+            assert!((0.0..=5.0).contains(&item.input[0]));
+        }
+    }
+
+    #[test]
+    fn smote_is_reproducible() {
+        let data = dataset(4, 12);
+        let a = smote(&data, 1.0, 2, &mut SeededRng::from_seed(9));
+        let b = smote(&data, 1.0, 2, &mut SeededRng::from_seed(9));
+        let inputs = |d: &[DataSetItem]| d.iter().map(|i| i.input.clone()).collect::<Vec<_>>();
+        assert_eq!(inputs(&a), inputs(&b));
+    }
+
+    #[test]
+    fn smote_with_single_item_duplicates_it() {
+        let data = dataset(1, 3);
+        let result = smote(&data, 1.0, 5, &mut SeededRng::from_seed(2));
+        let positives: Vec<&DataSetItem> =
+            result.iter().filter(|item| class_of(item) == 1).collect();
+        assert_eq!(positives.len(), 3);
+        assert!(positives.iter().all(|item| item.input == vec![0.0, 10.0]));
+    }
+}
@@ -0,0 +1,287 @@
+use std::f64::consts::PI;
+
+/// Learning rate as a function of the iteration. Implemented for closures, so
+/// `|i| 0.1 / (1.0 + i as f64)` can be used directly.
+pub trait LrSchedule {
+    fn learning_rate(&self, iteration: u64) -> f64;
+}
+
+impl<F: Fn(u64) -> f64> LrSchedule for F {
+    fn learning_rate(&self, iteration: u64) -> f64 {
+        self(iteration)
+    }
+}
+
+pub struct Constant(pub f64);
+
+impl LrSchedule for Constant {
+    fn learning_rate(&self, _iteration: u64) -> f64 {
+        self.0
+    }
+}
+
+/// Multiplies the rate by `factor` every `step_size` iterations. A
+/// `step_size` of 0 keeps the initial rate.
+pub struct StepDecay {
+    pub initial: f64,
+    pub factor: f64,
+    pub step_size: u64,
+}
+
+impl LrSchedule for StepDecay {
+    fn learning_rate(&self, iteration: u64) -> f64 {
+        match iteration.checked_div(self.step_size) {
+            Some(steps) => self.initial * self.factor.powi(steps as i32),
+            None => self.initial,
+        }
+    }
+}
+
+/// `initial * decay^iteration`
+pub struct ExponentialDecay {
+    pub initial: f64,
+    pub decay: f64,
+}
+
+impl LrSchedule for ExponentialDecay {
+    fn learning_rate(&self, iteration: u64) -> f64 {
+        self.initial * self.decay.powf(iteration as f64)
+    }
+}
+
+/// `initial / (1 + decay * iteration)`, but never below `min`.
+pub struct InverseTimeDecay {
+    pub initial: f64,
+    pub decay: f64,
+    pub min: f64,
+}
+
+impl LrSchedule for InverseTimeDecay {
+    fn learning_rate(&self, iteration: u64) -> f64 {
+        (self.initial / (1.0 + self.decay * iteration as f64)).max(self.min)
+    }
+}
+
+/// Cosine annealing from `max` to `min` over `period` iterations, restarting
+/// at `max` afterwards (SGDR). Each period is `period_multiplier` times as
+/// long as the previous one.
+pub struct CosineAnnealing {
+    max: f64,
+    min: f64,
+    period: f64,
+    period_multiplier: f64,
+}
+
+impl CosineAnnealing {
+    /// Panics unless `period_multiplier` is at least 1. A `period` of 0 is
+    /// treated as 1.
+    pub fn new(max: f64, min: f64, period: u64, period_multiplier: f64) -> CosineAnnealing {
+        assert!(
+            period_multiplier >= 1.0,
+            "Period multiplier {} is below 1",
+            period_multiplier
+        );
+        CosineAnnealing {
+            max,
+            min,
+            period: period.max(1) as f64,
+            period_multiplier,
+        }
+    }
+
+    /// First iteration of restart `k`, from the geometric series of the
+    /// period lengths.
+    fn restart(&self, k: f64) -> f64 {
+        let m = self.period_multiplier;
+        self.period * (m.powf(k) - 1.0) / (m - 1.0)
+    }
+}
+
+impl LrSchedule for CosineAnnealing {
+    fn learning_rate(&self, iteration: u64) -> f64 {
+        let t = iteration as f64;
+        let m = self.period_multiplier;
+        if m == 1.0 {
+            return cosine(self.max, self.min, (t % self.period) / self.period);
+        }
+        let mut k = (t * (m - 1.0) / self.period + 1.0).log(m).floor();
+        // Rounding in the logarithm can land next to the right period.
+        if self.restart(k) > t {
+            k -= 1.0;
+        } else if self.restart(k + 1.0) <= t {
+            k += 1.0;
+        }
+        let length = self.period * m.powf(k);
+        cosine(self.max, self.min, (t - self.restart(k)) / length)
+    }
+}
+
+/// Ramps the rate linearly up to the start of `schedule` over `warmup`
+/// iterations, then follows `schedule` from its iteration 0.
+pub struct LinearWarmup<S: LrSchedule> {
+    pub warmup: u64,
+    pub schedule: S,
+}
+
+impl<S: LrSchedule> LrSchedule for LinearWarmup<S> {
+    fn learning_rate(&self, iteration: u64) -> f64 {
+        if iteration < self.warmup {
+            self.schedule.learning_rate(0) * (iteration + 1) as f64 / self.warmup as f64
+        } else {
+            self.schedule.learning_rate(iteration - self.warmup)
+        }
+    }
+}
+
+/// One-cycle policy: cosine warmup from `max / div_factor` to `max` over the
+/// first `warmup_fraction` of `total` iterations, then cosine annealing down
+/// to `max / (div_factor * final_div_factor)`.
+pub struct OneCycle {
+    pub max: f64,
+    pub total: u64,
+    pub warmup_fraction: f64,
+    pub div_factor: f64,
+    pub final_div_factor: f64,
+}
+
+impl OneCycle {
+    /// The usual defaults: 30% warmup, starting at `max / 25` and ending at
+    /// `max / 25e4`.
+    pub fn new(max: f64, total: u64) -> OneCycle {
+        OneCycle {
+            max,
+            total,
+            warmup_fraction: 0.3,
+            div_factor: 25.0,
+            final_div_factor: 1e4,
+        }
+    }
+}
+
+impl LrSchedule for OneCycle {
+    fn learning_rate(&self, iteration: u64) -> f64 {
+        let initial = self.max / self.div_factor;
+        let last = initial / self.final_div_factor;
+        let warmup = (self.total as f64 * self.warmup_fraction).round();
+        let i = iteration as f64;
+        if i < warmup {
+            cosine(initial, self.max, i / warmup)
+        } else if iteration < self.total {
+            cosine(self.max, last, (i - warmup) / (self.total as f64 - warmup))
+        } else {
+            last
+        }
+    }
+}
+
+/// Interpolates from `start` (progress 0) to `end` (progress 1) along half a
+/// cosine.
+fn cosine(start: f64, end: f64, progress: f64) -> f64 {
+    end + (start - end) * (1.0 + (PI * progress).cos()) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-12, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn closures_are_schedules() {
+        let factor = 0.5;
+        let schedule = move |i: u64| factor / (i + 1) as f64;
+        assert_eq!(schedule.learning_rate(1), 0.25);
+        assert_eq!(Constant(0.1).learning_rate(1000), 0.1);
+    }
+
+    #[test]
+    fn decays() {
+        let step = StepDecay {
+            initial: 1.0,
+            factor: 0.5,
+            step_size: 10,
+        };
+        assert_eq!(step.learning_rate(9), 1.0);
+        assert_eq!(step.learning_rate(25), 0.25);
+
+        let constant = StepDecay {
+            step_size: 0,
+            ..step
+        };
+        assert_eq!(constant.learning_rate(25), 1.0);
+
+        let exponential = ExponentialDecay {
+            initial: 2.0,
+            decay: 0.5,
+        };
+        assert_eq!(exponential.learning_rate(3), 0.25);
+
+        let inverse = InverseTimeDecay {
+            initial: 0.5,
+            decay: 0.1,
+            min: 0.05,
+        };
+        assert_eq!(inverse.learning_rate(10), 0.25);
+        assert_eq!(inverse.learning_rate(1000), 0.05);
+    }
+
+    #[test]
+    fn cosine_annealing_restarts() {
+        let schedule = CosineAnnealing::new(1.0, 0.0, 10, 1.0);
+        assert_close(schedule.learning_rate(0), 1.0);
+        assert_close(schedule.learning_rate(5), 0.5);
+        assert_close(schedule.learning_rate(10), 1.0);
+        assert_close(schedule.learning_rate(25), 0.5);
+
+        let growing = CosineAnnealing::new(1.0, 0.0, 10, 2.0);
+        // Periods of 10, 20 and 40 iterations.
+        assert_close(growing.learning_rate(20), 0.5);
+        assert_close(growing.learning_rate(30), 1.0);
+        assert_close(growing.learning_rate(50), 0.5);
+        assert_close(growing.learning_rate(70), 1.0);
+        // Late in a long run, starting the 31st period.
+        assert_close(growing.learning_rate(10 * ((1 << 30) - 1)), 1.0);
+    }
+
+    #[test]
+    fn cosine_annealing_keeps_fractional_multipliers() {
+        // Periods of 10, 10.4 and 10.816 iterations; rounding each to whole
+        // iterations would keep them all at 10.
+        let schedule = CosineAnnealing::new(1.0, 0.0, 10, 1.04);
+        assert_close(schedule.learning_rate(15), cosine(1.0, 0.0, 5.0 / 10.4));
+        assert_close(schedule.learning_rate(25), cosine(1.0, 0.0, 4.6 / 10.816));
+    }
+
+    #[test]
+    #[should_panic(expected = "below 1")]
+    fn cosine_annealing_rejects_shrinking_periods() {
+        CosineAnnealing::new(1.0, 0.0, 10, 0.5);
+    }
+
+    #[test]
+    fn warmup_ramps_to_schedule() {
+        let schedule = LinearWarmup {
+            warmup: 4,
+            schedule: StepDecay {
+                initial: 1.0,
+                factor: 0.1,
+                step_size: 2,
+            },
+        };
+        let rates: Vec<f64> = (0..7).map(|i| schedule.learning_rate(i)).collect();
+        assert_eq!(rates[..5], [0.25, 0.5, 0.75, 1.0, 1.0]);
+        assert_close(rates[6], 0.1);
+    }
+
+    #[test]
+    fn one_cycle_peaks_after_warmup() {
+        let schedule = OneCycle::new(1.0, 100);
+        assert_close(schedule.learning_rate(0), 0.04);
+        assert_close(schedule.learning_rate(30), 1.0);
+        assert!(schedule.learning_rate(15) > 0.04 && schedule.learning_rate(15) < 1.0);
+        assert!(schedule.learning_rate(99) < schedule.learning_rate(60));
+        assert_close(schedule.learning_rate(100), 0.04 / 1e4);
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt;
+use std::mem::size_of;
+
+use crate::activation::ActivationFunctionKey;
+use crate::error::ErrorFunctionKey;
+use crate::layer::Shape;
+use crate::network::Network;
+
+pub struct LayerSummary {
+    pub name: String,
+    pub activation: Option<ActivationFunctionKey>,
+    pub input: Shape,
+    pub output: Shape,
+    /// Trainable parameters, biases included. Zero until initialized.
+    pub parameters: usize,
+}
+
+pub struct Summary {
+    pub loss: ErrorFunctionKey,
+    pub input: Shape,
+    pub layers: Vec<LayerSummary>,
+    pub total_parameters: usize,
+    /// Bytes held by the parameters.
+    pub memory_bytes: usize,
+    pub warnings: Vec<String>,
+}
+
+pub fn summarize(network: &Network) -> Summary {
+    let loss = network.error();
+    let mut warnings = Vec::new();
+    if network.input_dim() == 0 {
+        warnings.push("network is not initialized".to_string());
+    }
+
+    let mut layers = Vec::new();
+    let mut input = network.input_shape();
+    for (index, layer) in network.layers.iter().enumerate() {
+        let is_output = index == network.layers.len() - 1;
+        let activation = layer.activation();
+        let output = match layer.output_shape(input) {
+            Ok(output) => output,
+            Err(message) => {
+                warnings.push(format!("layer {}: {}", index, message));
+                input
+            }
+        };
+        layers.push(LayerSummary {
+            name: layer.name(),
+            activation,
+            input,
+            output,
+            parameters: layer.parameters().items.len(),
+        });
+        warnings.extend(
+            layer_warnings(activation, output.size(), is_output, loss)
+                .into_iter()
+                .map(|warning| format!("layer {}: {}", index, warning)),
+        );
+        input = output;
+    }
+    if layers.is_empty() {
+        warnings.push("network has no layers".to_string());
+    }
+
+    let total_parameters = layers.iter().map(|l| l.parameters).sum();
+    Summary {
+        loss,
+        input: network.input_shape(),
+        total_parameters,
+        layers,
+        memory_bytes: total_parameters * size_of::<f64>(),
+        warnings,
+    }
+}
+
+fn layer_warnings(
+    activation: Option<ActivationFunctionKey>,
+    units: usize,
+    is_output: bool,
+    loss: ErrorFunctionKey,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let activation = match activation {
+        Some(activation) => activation,
+        None => {
+            if is_output {
+                warnings.push("the output layer has no activation".to_string());
+            }
+            return warnings;
+        }
+    };
+    if activation == ActivationFunctionKey::Softmax {
+        if !is_output {
+            warnings.push("softmax is only supported on the output layer".to_string());
+        } else if loss != ErrorFunctionKey::CrossEntropy {
+            warnings.push("softmax requires the cross-entropy loss".to_string());
+        }
+    }
+    if is_output && loss == ErrorFunctionKey::LogLoss && units != 1 {
+        warnings.push(format!("log loss only uses the first of {} outputs", units));
+    }
+    if is_output
+        && loss != ErrorFunctionKey::MeanSquared
+        && matches!(
+            activation,
+            ActivationFunctionKey::ReLu
+                | ActivationFunctionKey::TanH
+                | ActivationFunctionKey::Linear
+        )
+    {
+        warnings.push(format!(
+            "{:?} outputs are not probabilities as {:?} expects",
+            activation, loss
+        ));
+    }
+    warnings
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Layer\tType\tActivation\tShape\tParameters")?;
+        for (index, layer) in self.layers.iter().enumerate() {
+            let activation = match layer.activation {
+                Some(activation) => format!("{:?}", activation),
+                None => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{} -> {}\t{}",
+                index, layer.name, activation, layer.input, layer.output, layer.parameters
+            )?;
+        }
+        writeln!(f, "Loss: {:?}", self.loss)?;
+        writeln!(f, "Total parameters: {}", self.total_parameters)?;
+        write!(f, "Memory: {} bytes", self.memory_bytes)?;
+        for warning in &self.warnings {
+            write!(f, "\nWarning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conv::Window;
+    use crate::network::create_network;
+
+    #[test]
+    fn counts_parameters() {
+        let mut network = create_network(ErrorFunctionKey::CrossEntropy);
+        network.add(ActivationFunctionKey::ReLu, 4);
+        network.add(ActivationFunctionKey::Softmax, 3);
+        network.initialize(2, None);
+
+        let summary = network.summary();
+        let shapes: Vec<(usize, usize, usize)> = summary
+            .layers
+            .iter()
+            .map(|l| (l.input.size(), l.output.size(), l.parameters))
+            .collect();
+        assert_eq!(shapes, vec![(2, 4, 12), (4, 3, 15)]);
+        assert_eq!(summary.total_parameters, 27);
+        assert_eq!(summary.memory_bytes, 27 * 8);
+        assert!(summary.warnings.is_empty());
+        assert!(summary.to_string().contains("Total parameters: 27"));
+    }
+
+    #[test]
+    fn reports_spatial_shapes() {
+        let mut network = create_network(ErrorFunctionKey::CrossEntropy);
+        network.add_conv2d(
+            ActivationFunctionKey::ReLu,
+            4,
+            Window {
+                kernel: 3,
+                stride: 1,
+                padding: 0,
+            },
+        );
+        network.add_flatten();
+        network.add(ActivationFunctionKey::Softmax, 2);
+        network.initialize_with_shape(
+            Shape {
+                channels: 2,
+                height: 5,
+                width: 5,
+            },
+            None,
+        );
+
+        let summary = network.summary();
+        let parameters: Vec<usize> = summary.layers.iter().map(|l| l.parameters).collect();
+        assert_eq!(parameters, vec![4 * (2 * 9 + 1), 0, 2 * 37]);
+        let text = summary.to_string();
+        assert!(text.contains("Conv2d 3x3/1 pad 0\tReLu\t2x5x5 -> 4x3x3\t76"));
+        assert!(text.contains("Flatten\t-\t4x3x3 -> 36\t0"));
+    }
+
+    #[test]
+    fn flags_configuration_problems() {
+        let mut network = create_network(ErrorFunctionKey::LogLoss);
+        network.add(ActivationFunctionKey::Softmax, 4);
+        network.add(ActivationFunctionKey::TanH, 2);
+
+        let summary = network.summary();
+        assert_eq!(
+            summary.warnings,
+            vec![
+                "network is not initialized",
+                "layer 0: softmax is only supported on the output layer",
+                "layer 1: log loss only uses the first of 2 outputs",
+                "layer 1: TanH outputs are not probabilities as LogLoss expects",
+            ]
+        );
+        assert!(summary
+            .to_string()
+            .ends_with("Warning: layer 1: TanH outputs are not probabilities as LogLoss expects"));
+    }
+
+    #[test]
+    fn flags_output_layer_without_activation() {
+        let mut network = create_network(ErrorFunctionKey::MeanSquared);
+        network.add(ActivationFunctionKey::Sigmoid, 2);
+        network.add_dropout(0.5);
+        network.initialize(3, None);
+
+        let summary = network.summary();
+        assert_eq!(
+            summary.warnings,
+            vec!["layer 1: the output layer has no activation"]
+        );
+        assert!(summary.to_string().contains("Dropout 0.5\t-\t2 -> 2\t0"));
+    }
+}
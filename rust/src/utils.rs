@@ -1,9 +1,11 @@
+use rand::seq::index::sample;
 use rand::Rng;
-use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
 
+use crate::initializer::{initialize_weights, InitializerKey};
 use crate::matrix::Matrix;
 
 #[derive(Debug, Clone)]
@@ -18,8 +20,34 @@ pub fn one_hot_encode(label: f64, len: usize) -> Vec<f64> {
     vector
 }
 
-pub fn is_correct_category(estimate: &Matrix, output: &Vec<f64>) -> bool {
-    let label = output.iter().position(|x| x.round() == 1.0).unwrap();
+/// Index of the largest value, preferring the first one on ties.
+pub fn argmax(values: &[f64]) -> usize {
+    let mut best = 0;
+    for (i, value) in values.iter().enumerate() {
+        if *value > values[best] {
+            best = i;
+        }
+    }
+    best
+}
+
+/// 1 if `value` is strictly above `cutoff`, else 0, as in `metrics.ts`.
+pub fn binary_class(value: f64, cutoff: f64) -> usize {
+    if value > cutoff {
+        1
+    } else {
+        0
+    }
+}
+
+/// Compares a one-hot label with the largest estimate. Ties go to the last
+/// maximum. Panics if the label is not one-hot.
+pub fn is_correct_category(estimate: &Matrix, output: &[f64]) -> bool {
+    let mut hot = output.iter().enumerate().filter(|(_, x)| x.round() != 0.0);
+    let label = match (hot.next(), hot.next()) {
+        (Some((label, x)), None) if x.round() == 1.0 => label,
+        _ => panic!("Label {:?} is not one-hot encoded", output),
+    };
 
     let index_of_max = estimate
         .items
@@ -32,23 +60,238 @@ pub fn is_correct_category(estimate: &Matrix, output: &Vec<f64>) -> bool {
     label == index_of_max
 }
 
-pub fn generate_batch(dataset: &Vec<DataSetItem>, size: u32) -> Vec<DataSetItem> {
-    let mut result: Vec<DataSetItem> = Vec::new();
+pub fn generate_batch(dataset: &[DataSetItem], size: u32, rng: &mut impl Rng) -> Vec<DataSetItem> {
+    if size as usize > dataset.len() {
+        panic!(
+            "Batch size {} is larger than the dataset ({})",
+            size,
+            dataset.len()
+        )
+    }
+    sample(rng, dataset.len(), size as usize)
+        .iter()
+        .map(|i| dataset[i].clone())
+        .collect()
+}
 
-    let mut used_indices: HashMap<usize, bool> = HashMap::new();
+/// Columns are 0-based indices into each line.
+pub struct CsvSchema {
+    /// Labels are output in this order.
+    pub label_columns: Vec<usize>,
+    /// One-hot encodes a single label column into this many classes.
+    pub one_hot_classes: Option<usize>,
+    pub skip_columns: Vec<usize>,
+    pub has_header: bool,
+    pub delimiter: char,
+}
+
+impl Default for CsvSchema {
+    fn default() -> CsvSchema {
+        CsvSchema {
+            label_columns: vec![0],
+            one_hot_classes: None,
+            skip_columns: Vec::new(),
+            has_header: false,
+            delimiter: ',',
+        }
+    }
+}
+
+/// Line numbers are 1-based and count the header; column numbers are
+/// 0-based, like those of `CsvSchema`.
+#[derive(Debug)]
+pub enum CsvError {
+    Io(io::Error),
+    Schema(String),
+    /// A label column past the width of the file.
+    LabelColumn {
+        column: usize,
+        columns: usize,
+    },
+    /// A skipped column past the width of the file.
+    SkipColumn {
+        column: usize,
+        columns: usize,
+    },
+    ColumnCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    InvalidValue {
+        line: usize,
+        column: usize,
+        value: String,
+    },
+    InvalidLabel {
+        line: usize,
+        value: f64,
+    },
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CsvError::Io(e) => write!(f, "{}", e),
+            CsvError::Schema(message) => write!(f, "invalid schema: {}", message),
+            CsvError::LabelColumn { column, columns } => write!(
+                f,
+                "label column {} is out of range for {} columns",
+                column, columns
+            ),
+            CsvError::SkipColumn { column, columns } => write!(
+                f,
+                "skip column {} is out of range for {} columns",
+                column, columns
+            ),
+            CsvError::ColumnCount {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected {} columns, found {}",
+                line, expected, found
+            ),
+            CsvError::InvalidValue {
+                line,
+                column,
+                value,
+            } => write!(
+                f,
+                "line {}, column {}: could not parse {:?} as a number",
+                line, column, value
+            ),
+            CsvError::InvalidLabel { line, value } => {
+                write!(f, "line {}: label {} is not a valid class", line, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CsvError {}
+
+impl From<io::Error> for CsvError {
+    fn from(e: io::Error) -> CsvError {
+        CsvError::Io(e)
+    }
+}
+
+pub fn read_csv<P>(path: P, schema: &CsvSchema) -> Result<Vec<DataSetItem>, CsvError>
+where
+    P: AsRef<Path>,
+{
+    let file = File::open(path)?;
+    parse_csv(io::BufReader::new(file), schema)
+}
+
+/// Parses rows one at a time. Blank lines are ignored. The first line, header or not,
+/// sets the number of columns every line must have.
+pub fn parse_csv<R: BufRead>(reader: R, schema: &CsvSchema) -> Result<Vec<DataSetItem>, CsvError> {
+    if schema.label_columns.is_empty() {
+        return Err(CsvError::Schema("no label columns".to_string()));
+    }
+    if schema.one_hot_classes.is_some() && schema.label_columns.len() != 1 {
+        return Err(CsvError::Schema(
+            "one-hot encoding needs exactly one label column".to_string(),
+        ));
+    }
+    if let Some(column) = schema
+        .label_columns
+        .iter()
+        .find(|c| schema.skip_columns.contains(c))
+    {
+        return Err(CsvError::Schema(format!(
+            "column {} is both a label and skipped",
+            column
+        )));
+    }
+
+    let mut result = Vec::new();
+    let mut column_count: Option<usize> = None;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_number = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let values: Vec<&str> = line.split(schema.delimiter).collect();
+        let expected = match column_count {
+            Some(count) => count,
+            None => {
+                if let Some(&column) = schema.label_columns.iter().find(|&&c| c >= values.len()) {
+                    return Err(CsvError::LabelColumn {
+                        column,
+                        columns: values.len(),
+                    });
+                }
+                if let Some(&column) = schema.skip_columns.iter().find(|&&c| c >= values.len()) {
+                    return Err(CsvError::SkipColumn {
+                        column,
+                        columns: values.len(),
+                    });
+                }
+                *column_count.insert(values.len())
+            }
+        };
+        if values.len() != expected {
+            return Err(CsvError::ColumnCount {
+                line: line_number,
+                expected,
+                found: values.len(),
+            });
+        }
+        if schema.has_header && index == 0 {
+            continue;
+        }
 
-    while result.len() < size as usize {
-        'inner: loop {
-            let next_i: usize = rand::thread_rng().gen_range(0..dataset.len());
-            if used_indices.contains_key(&next_i) {
+        let mut input = Vec::new();
+        let mut output = vec![0.0; schema.label_columns.len()];
+        for (column, value) in values.into_iter().enumerate() {
+            if schema.skip_columns.contains(&column) {
                 continue;
             }
-            used_indices.insert(next_i, true);
-            result.push(dataset[next_i].clone());
-            break 'inner;
+            let parsed = value
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| CsvError::InvalidValue {
+                    line: line_number,
+                    column,
+                    value: value.to_string(),
+                })?;
+            match schema.label_columns.iter().position(|&c| c == column) {
+                Some(position) => output[position] = parsed,
+                None => input.push(parsed),
+            }
         }
+
+        let output = match schema.one_hot_classes {
+            Some(classes) => {
+                let label = output[0];
+                if label < 0.0 || label.fract() != 0.0 || label as usize >= classes {
+                    return Err(CsvError::InvalidLabel {
+                        line: line_number,
+                        value: label,
+                    });
+                }
+                one_hot_encode(label, classes)
+            }
+            None => output,
+        };
+        result.push(DataSetItem { input, output });
     }
-    result
+    Ok(result)
+}
+
+#[deprecated(note = "use initializer::initialize_weights with InitializerKey::Uniform(0.5)")]
+pub fn random_weights(rows: usize, cols: usize) -> Vec<Vec<f64>> {
+    initialize_weights(
+        InitializerKey::Uniform(0.5),
+        rows,
+        cols,
+        &mut rand::thread_rng(),
+    )
 }
 
 pub fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
@@ -59,15 +302,176 @@ where
     Ok(io::BufReader::new(file).lines())
 }
 
-pub fn random_weights(rows: usize, cols: usize) -> Vec<Vec<f64>> {
-    let mut result: Vec<Vec<f64>> = Vec::new();
-    for _ in 0..rows {
-        let mut row = Vec::new();
-        row.push(0.0);
-        for _ in 1..cols {
-            row.push(rand::thread_rng().gen_range(-0.5..0.5))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::SeededRng;
+
+    fn dataset(len: usize) -> Vec<DataSetItem> {
+        (0..len)
+            .map(|i| DataSetItem {
+                input: vec![i as f64],
+                output: vec![0.0],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn one_hot_encode_sets_label() {
+        assert_eq!(one_hot_encode(2.0, 4), vec![0.0, 0.0, 1.0, 0.0]);
+        assert_eq!(one_hot_encode(0.0, 1), vec![1.0]);
+    }
+
+    #[test]
+    fn is_correct_category_compares_argmax() {
+        let estimate = Matrix::from(&vec![0.1, 0.6, 0.3]);
+        assert!(is_correct_category(&estimate, &[0.0, 1.0, 0.0]));
+        assert!(!is_correct_category(&estimate, &[0.0, 0.0, 1.0]));
+
+        let tie = Matrix::from(&vec![0.4, 0.4, 0.2]);
+        assert!(is_correct_category(&tie, &[0.0, 1.0, 0.0]));
+        assert!(!is_correct_category(&tie, &[1.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    #[should_panic(expected = "not one-hot encoded")]
+    fn is_correct_category_rejects_empty_labels() {
+        is_correct_category(&Matrix::from(&vec![0.1, 0.9]), &[0.0, 0.0]);
+    }
+
+    #[test]
+    fn binary_class_is_strictly_above_the_cutoff() {
+        assert_eq!(binary_class(0.7, 0.5), 1);
+        assert_eq!(binary_class(0.5, 0.5), 0);
+        assert_eq!(binary_class(0.2, 0.1), 1);
+    }
+
+    #[test]
+    fn generate_batch_has_requested_size_without_duplicates() {
+        let data = dataset(50);
+        let mut rng = SeededRng::from_seed(0);
+        for size in [1, 10, 50] {
+            let batch = generate_batch(&data, size, &mut rng);
+            assert_eq!(batch.len(), size as usize);
+
+            let mut seen: Vec<f64> = batch.iter().map(|item| item.input[0]).collect();
+            seen.sort_by(|a, b| a.total_cmp(b));
+            seen.dedup();
+            assert_eq!(seen.len(), size as usize);
+        }
+    }
+
+    #[test]
+    fn parse_csv_with_one_hot_labels() {
+        let schema = CsvSchema {
+            one_hot_classes: Some(3),
+            has_header: true,
+            ..CsvSchema::default()
+        };
+        let data = "label,a,b\n2,0.5,1\n\n0, 3 ,4\n";
+        let items = parse_csv(data.as_bytes(), &schema).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].input, vec![0.5, 1.0]);
+        assert_eq!(items[0].output, vec![0.0, 0.0, 1.0]);
+        assert_eq!(items[1].input, vec![3.0, 4.0]);
+        assert_eq!(items[1].output, vec![1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn parse_csv_with_skipped_and_multiple_label_columns() {
+        let schema = CsvSchema {
+            label_columns: vec![3, 1],
+            skip_columns: vec![0],
+            delimiter: ';',
+            ..CsvSchema::default()
+        };
+        let items = parse_csv("7;1;2;3;4".as_bytes(), &schema).unwrap();
+        assert_eq!(items[0].input, vec![2.0, 4.0]);
+        assert_eq!(items[0].output, vec![3.0, 1.0]);
+    }
+
+    #[test]
+    fn parse_csv_rejects_label_columns_past_the_row() {
+        let schema = CsvSchema {
+            label_columns: vec![0, 4],
+            has_header: true,
+            ..CsvSchema::default()
+        };
+        let error = parse_csv(
+            "y,a,b
+1,2,3
+"
+            .as_bytes(),
+            &schema,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "label column 4 is out of range for 3 columns"
+        );
+    }
+
+    #[test]
+    fn parse_csv_rejects_skip_columns_past_the_row() {
+        let schema = CsvSchema {
+            skip_columns: vec![3],
+            ..CsvSchema::default()
+        };
+        let error = parse_csv("1,2,3\n".as_bytes(), &schema).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "skip column 3 is out of range for 3 columns"
+        );
+    }
+
+    #[test]
+    fn parse_csv_reports_line_numbers() {
+        let schema = CsvSchema::default();
+        let error = parse_csv("1,2\n3,x\n".as_bytes(), &schema).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 2, column 1: could not parse \"x\" as a number"
+        );
+
+        let error = parse_csv("1,2\n3,4,5\n".as_bytes(), &schema).unwrap_err();
+        assert!(matches!(
+            error,
+            CsvError::ColumnCount {
+                line: 2,
+                expected: 2,
+                found: 3
+            }
+        ));
+
+        let schema = CsvSchema {
+            one_hot_classes: Some(2),
+            ..CsvSchema::default()
+        };
+        let error = parse_csv("1,2\n2,4\n".as_bytes(), &schema).unwrap_err();
+        assert!(matches!(error, CsvError::InvalidLabel { line: 2, .. }));
+    }
+
+    #[test]
+    fn read_csv_reports_missing_file() {
+        let error = read_csv("./does-not-exist.csv", &CsvSchema::default()).unwrap_err();
+        assert!(matches!(error, CsvError::Io(_)));
+    }
+
+    #[test]
+    #[should_panic(expected = "larger than the dataset")]
+    fn generate_batch_rejects_oversized_batches() {
+        generate_batch(&dataset(3), 4, &mut SeededRng::from_seed(0));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn random_weights_have_zero_bias() {
+        let weights = random_weights(3, 5);
+        assert_eq!(weights.len(), 3);
+        for row in weights {
+            assert_eq!(row.len(), 5);
+            assert_eq!(row[0], 0.0);
+            assert!(row[1..].iter().all(|w| (-0.5..0.5).contains(w)));
         }
-        result.push(row)
     }
-    result
 }
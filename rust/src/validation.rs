@@ -0,0 +1,352 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::network::Network;
+use crate::utils::{argmax, binary_class, DataSetItem};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Evaluation {
+    pub loss: f64,
+    pub accuracy: f64,
+}
+
+pub struct Split {
+    pub train: Vec<DataSetItem>,
+    pub validation: Vec<DataSetItem>,
+    pub test: Vec<DataSetItem>,
+}
+
+pub struct CrossValidationResult {
+    pub folds: Vec<Evaluation>,
+    pub mean: Evaluation,
+    pub std: Evaluation,
+}
+
+/// Mean loss and accuracy of the network over `data`. Multi-output networks
+/// are scored by argmax, single-output ones by whether the output is above
+/// 0.5.
+pub fn evaluate(network: &Network, data: &[DataSetItem]) -> Evaluation {
+    let mut correct_count = 0;
+    let mut total_loss = 0.0;
+    for item in data {
+        let result = network.evaluate_item(item);
+        let last = &result.results[result.results.len() - 1];
+
+        total_loss += result.loss;
+        if is_correct(&last.activated.items, &item.output) {
+            correct_count += 1;
+        }
+    }
+    Evaluation {
+        loss: total_loss / data.len() as f64,
+        accuracy: correct_count as f64 / data.len() as f64,
+    }
+}
+
+/// Scores like `metrics`: a single output is positive strictly above 0.5,
+/// and ties between several outputs go to the first.
+fn is_correct(estimate: &[f64], output: &[f64]) -> bool {
+    if output.len() == 1 {
+        return binary_class(estimate[0], 0.5) == binary_class(output[0], 0.5);
+    }
+    argmax(estimate) == argmax(output)
+}
+
+/// The class used for stratification: the argmax of one-hot outputs, or the
+/// rounded value of a single output.
+pub fn class_of(item: &DataSetItem) -> usize {
+    if item.output.len() == 1 {
+        return item.output[0].round().max(0.0) as usize;
+    }
+    argmax(&item.output)
+}
+
+/// Splits `data` into train, validation and test sets. The fractions refer
+/// to the validation and test sets; the rest is used for training. When
+/// `stratify` is set every class is split with the same proportions.
+pub fn train_validation_test_split(
+    data: Vec<DataSetItem>,
+    validation: f64,
+    test: f64,
+    stratify: bool,
+    rng: &mut impl Rng,
+) -> Split {
+    if validation < 0.0 || test < 0.0 || validation + test > 1.0 {
+        panic!("Invalid split fractions {} and {}", validation, test);
+    }
+
+    let mut assignment = vec![0_u8; data.len()];
+    for group in groups(&data, stratify, rng) {
+        let n = group.len() as f64;
+        let test_count = (n * test).round() as usize;
+        let validation_count = ((n * validation).round() as usize).min(group.len() - test_count);
+        for (k, index) in group.into_iter().enumerate() {
+            assignment[index] = if k < test_count {
+                2
+            } else if k < test_count + validation_count {
+                1
+            } else {
+                0
+            };
+        }
+    }
+
+    let mut split = Split {
+        train: Vec::new(),
+        validation: Vec::new(),
+        test: Vec::new(),
+    };
+    for (item, target) in data.into_iter().zip(assignment) {
+        match target {
+            0 => split.train.push(item),
+            1 => split.validation.push(item),
+            _ => split.test.push(item),
+        }
+    }
+    split
+}
+
+/// Indices of the validation items of each fold. Every item appears in
+/// exactly one fold.
+pub fn k_fold(
+    data: &[DataSetItem],
+    k: usize,
+    stratify: bool,
+    rng: &mut impl Rng,
+) -> Vec<Vec<usize>> {
+    if k < 2 || k > data.len() {
+        panic!("Invalid fold count {} for {} items", k, data.len());
+    }
+    let mut folds = vec![Vec::new(); k];
+    let order: Vec<usize> = groups(data, stratify, rng).concat();
+    for (position, index) in order.into_iter().enumerate() {
+        folds[position % k].push(index);
+    }
+    folds
+}
+
+/// Runs `train` once per fold with that fold held out for validation. The
+/// closure is expected to build and train a fresh network and return its
+/// evaluation on the held-out items.
+pub fn cross_validate<F>(
+    data: &[DataSetItem],
+    k: usize,
+    stratify: bool,
+    rng: &mut impl Rng,
+    mut train: F,
+) -> CrossValidationResult
+where
+    F: FnMut(&[DataSetItem], &[DataSetItem]) -> Evaluation,
+{
+    let folds = k_fold(data, k, stratify, rng);
+    let mut results = Vec::with_capacity(k);
+    for fold in &folds {
+        let mut in_fold = vec![false; data.len()];
+        for &index in fold {
+            in_fold[index] = true;
+        }
+        let mut train_data = Vec::with_capacity(data.len() - fold.len());
+        let mut validation_data = Vec::with_capacity(fold.len());
+        for (index, item) in data.iter().enumerate() {
+            if in_fold[index] {
+                validation_data.push(item.clone());
+            } else {
+                train_data.push(item.clone());
+            }
+        }
+        results.push(train(&train_data, &validation_data));
+    }
+
+    let losses: Vec<f64> = results.iter().map(|r| r.loss).collect();
+    let accuracies: Vec<f64> = results.iter().map(|r| r.accuracy).collect();
+    let (loss_mean, loss_std) = mean_and_std(&losses);
+    let (accuracy_mean, accuracy_std) = mean_and_std(&accuracies);
+
+    CrossValidationResult {
+        folds: results,
+        mean: Evaluation {
+            loss: loss_mean,
+            accuracy: accuracy_mean,
+        },
+        std: Evaluation {
+            loss: loss_std,
+            accuracy: accuracy_std,
+        },
+    }
+}
+
+fn groups(data: &[DataSetItem], stratify: bool, rng: &mut impl Rng) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    if stratify {
+        for (index, item) in data.iter().enumerate() {
+            let class = class_of(item);
+            if groups.len() <= class {
+                groups.resize(class + 1, Vec::new());
+            }
+            groups[class].push(index);
+        }
+    } else {
+        groups.push((0..data.len()).collect());
+    }
+    for group in groups.iter_mut() {
+        group.shuffle(rng);
+    }
+    groups
+}
+
+fn mean_and_std(values: &[f64]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+    (mean, variance.sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activation::ActivationFunctionKey;
+    use crate::error::ErrorFunctionKey;
+    use crate::metrics::{binary_classification_accuracy, classification_accuracy, predictions};
+    use crate::network::create_network;
+    use crate::random::SeededRng;
+
+    fn dataset(positives: usize, negatives: usize) -> Vec<DataSetItem> {
+        (0..positives + negatives)
+            .map(|i| DataSetItem {
+                input: vec![i as f64],
+                output: vec![if i < positives { 1.0 } else { 0.0 }],
+            })
+            .collect()
+    }
+
+    fn count_positive(items: &[DataSetItem]) -> usize {
+        items.iter().filter(|item| class_of(item) == 1).count()
+    }
+
+    #[test]
+    fn class_of_handles_binary_and_one_hot() {
+        let binary = DataSetItem {
+            input: vec![],
+            output: vec![1.0],
+        };
+        let one_hot = DataSetItem {
+            input: vec![],
+            output: vec![0.0, 0.0, 1.0],
+        };
+        assert_eq!(class_of(&binary), 1);
+        assert_eq!(class_of(&one_hot), 2);
+    }
+
+    #[test]
+    fn split_sizes_and_disjointness() {
+        let mut rng = SeededRng::from_seed(0);
+        let split = train_validation_test_split(dataset(30, 70), 0.2, 0.1, false, &mut rng);
+        assert_eq!(split.train.len(), 70);
+        assert_eq!(split.validation.len(), 20);
+        assert_eq!(split.test.len(), 10);
+
+        let mut ids: Vec<f64> = [split.train, split.validation, split.test]
+            .concat()
+            .iter()
+            .map(|item| item.input[0])
+            .collect();
+        ids.sort_by(|a, b| a.total_cmp(b));
+        ids.dedup();
+        assert_eq!(ids.len(), 100);
+    }
+
+    #[test]
+    fn stratified_split_preserves_class_ratio() {
+        let mut rng = SeededRng::from_seed(1);
+        let split = train_validation_test_split(dataset(20, 80), 0.25, 0.25, true, &mut rng);
+        assert_eq!(count_positive(&split.validation), 5);
+        assert_eq!(count_positive(&split.test), 5);
+        assert_eq!(count_positive(&split.train), 10);
+        assert_eq!(split.train.len(), 50);
+    }
+
+    #[test]
+    fn k_fold_partitions_items() {
+        let data = dataset(10, 20);
+        let folds = k_fold(&data, 5, true, &mut SeededRng::from_seed(2));
+        assert_eq!(folds.len(), 5);
+
+        let mut all: Vec<usize> = folds.concat();
+        all.sort();
+        assert_eq!(all, (0..30).collect::<Vec<usize>>());
+        for fold in &folds {
+            assert_eq!(fold.len(), 6);
+            assert_eq!(fold.iter().filter(|&&i| i < 10).count(), 2);
+        }
+    }
+
+    #[test]
+    fn cross_validate_aggregates_folds() {
+        let data = dataset(5, 5);
+        let mut calls = 0;
+        let result = cross_validate(
+            &data,
+            5,
+            false,
+            &mut SeededRng::from_seed(3),
+            |train, validation| {
+                calls += 1;
+                assert_eq!(train.len(), 8);
+                assert_eq!(validation.len(), 2);
+                Evaluation {
+                    loss: calls as f64,
+                    accuracy: 0.5,
+                }
+            },
+        );
+        assert_eq!(calls, 5);
+        assert_eq!(result.folds.len(), 5);
+        assert_eq!(result.mean.loss, 3.0);
+        assert!((result.std.loss - 2_f64.sqrt()).abs() < 1e-12);
+        assert_eq!(result.mean.accuracy, 0.5);
+        assert_eq!(result.std.accuracy, 0.0);
+    }
+
+    fn zero_network(error: ErrorFunctionKey, output: ActivationFunctionKey, units: u32) -> Network {
+        let mut network = create_network(error);
+        network.add(output, units);
+        network.initialize(1, Some(vec![vec![vec![0.0, 0.0]; units as usize]]));
+        network
+    }
+
+    fn items(outputs: &[Vec<f64>]) -> Vec<DataSetItem> {
+        outputs
+            .iter()
+            .map(|output| DataSetItem {
+                input: vec![1.0],
+                output: output.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn evaluate_scores_like_metrics() {
+        // Zero weights: tied softmax outputs and a sigmoid output of exactly 0.5.
+        let network = zero_network(
+            ErrorFunctionKey::CrossEntropy,
+            ActivationFunctionKey::Softmax,
+            2,
+        );
+        let data = items(&[vec![1.0, 0.0], vec![0.0, 1.0], vec![0.0, 1.0]]);
+        let accuracy = evaluate(&network, &data).accuracy;
+        assert_eq!(accuracy, 1.0 / 3.0);
+        assert_eq!(
+            accuracy,
+            classification_accuracy(&predictions(&network, &data))
+        );
+
+        let network = zero_network(ErrorFunctionKey::LogLoss, ActivationFunctionKey::Sigmoid, 1);
+        let data = items(&[vec![0.0], vec![1.0], vec![1.0]]);
+        let accuracy = evaluate(&network, &data).accuracy;
+        assert_eq!(accuracy, 1.0 / 3.0);
+        assert_eq!(
+            accuracy,
+            binary_classification_accuracy(&predictions(&network, &data), 0.5)
+        );
+    }
+}
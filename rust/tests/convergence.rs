@@ -0,0 +1,276 @@
+use rust::activation::ActivationFunctionKey;
+use rust::builder::NetworkBuilder;
+use rust::error::ErrorFunctionKey;
+use rust::network::create_network;
+use rust::optimizer::{sgd_optimizer, Optimizer};
+use rust::random::SeededRng;
+use rust::regularizer::RegularizerKey;
+use rust::schedule::Constant;
+use rust::utils::{argmax, generate_batch, DataSetItem};
+use rust::validation::{cross_validate, evaluate};
+
+fn xor_dataset() -> Vec<DataSetItem> {
+    [
+        ([1.0, 1.0], 0.0),
+        ([1.0, 0.0], 1.0),
+        ([0.0, 1.0], 1.0),
+        ([0.0, 0.0], 0.0),
+    ]
+    .iter()
+    .map(|(input, output)| DataSetItem {
+        input: input.to_vec(),
+        output: vec![*output],
+    })
+    .collect()
+}
+
+#[test]
+fn xor_reaches_low_loss() {
+    let w1 = vec![vec![0.5, 0.5, 0.5], vec![-0.5, -0.5, -0.5]];
+    let w2 = vec![vec![-0.5, 0.5, 0.5]];
+
+    let mut network = create_network(ErrorFunctionKey::LogLoss);
+    network.add(ActivationFunctionKey::TanH, 2);
+    network.add(ActivationFunctionKey::Sigmoid, 1);
+    network.initialize(2, Some(vec![w1, w2]));
+
+    let mut optimizer = sgd_optimizer(Constant(1.0));
+    let initial_loss = network.compute_gradients(&xor_dataset()).loss;
+    for i in 0..300 {
+        let data = network.compute_gradients(&xor_dataset());
+        optimizer.do_update(&data, &mut network, i);
+    }
+    let final_loss = network.compute_gradients(&xor_dataset()).loss;
+
+    assert!(final_loss < 0.05, "loss {}", final_loss);
+    assert!(final_loss < initial_loss);
+    for item in xor_dataset() {
+        assert_eq!(network.predict_class(&item.input) as f64, item.output[0]);
+    }
+}
+
+#[test]
+fn seeded_runs_are_reproducible() {
+    fn train(seed: u64) -> (f64, Vec<f64>) {
+        let mut network = create_network(ErrorFunctionKey::LogLoss);
+        network.seed(seed);
+        network.add(ActivationFunctionKey::TanH, 3);
+        network.add(ActivationFunctionKey::Sigmoid, 1);
+        network.initialize(2, None);
+
+        let mut optimizer = sgd_optimizer(Constant(1.0));
+        for i in 0..500 {
+            let batch = generate_batch(&xor_dataset(), 3, &mut network.rng);
+            let data = network.compute_gradients(&batch);
+            optimizer.do_update(&data, &mut network, i);
+        }
+        let loss = network.compute_gradients(&xor_dataset()).loss;
+        (loss, network.layers[0].parameters().items.clone())
+    }
+
+    let (loss, weights) = train(11);
+    assert!(loss < 0.1, "loss {}", loss);
+    assert_eq!((loss, weights), train(11));
+    assert_ne!(train(11).1, train(12).1);
+}
+
+fn cluster_dataset() -> Vec<DataSetItem> {
+    let centers = [[1.0, 0.0], [-1.0, 1.0], [0.0, -1.0]];
+    let mut dataset = Vec::new();
+    for (label, center) in centers.iter().enumerate() {
+        for k in 0..5 {
+            let offset = (k as f64 - 2.0) * 0.1;
+            let mut output = vec![0.0; centers.len()];
+            output[label] = 1.0;
+            dataset.push(DataSetItem {
+                input: vec![center[0] + offset, center[1] - offset],
+                output,
+            });
+        }
+    }
+    dataset
+}
+
+#[test]
+fn softmax_classifier_separates_clusters() {
+    let dataset = cluster_dataset();
+
+    let mut network = create_network(ErrorFunctionKey::CrossEntropy);
+    network.add(ActivationFunctionKey::Sigmoid, 4);
+    network.add(ActivationFunctionKey::Softmax, 3);
+    network.initialize(
+        2,
+        Some(vec![
+            vec![
+                vec![0.0, 0.3, -0.2],
+                vec![0.0, -0.4, 0.1],
+                vec![0.0, 0.2, 0.4],
+                vec![0.0, -0.1, -0.3],
+            ],
+            vec![
+                vec![0.0, 0.2, -0.3, 0.1, 0.4],
+                vec![0.0, -0.2, 0.3, -0.4, 0.1],
+                vec![0.0, 0.1, 0.2, 0.3, -0.2],
+            ],
+        ]),
+    );
+
+    let mut optimizer = sgd_optimizer(Constant(0.5));
+    for i in 0..500 {
+        let data = network.compute_gradients(&dataset);
+        optimizer.do_update(&data, &mut network, i);
+    }
+
+    assert!(network.compute_gradients(&dataset).loss < 0.1);
+    for item in &dataset {
+        assert_eq!(network.predict_class(&item.input), argmax(&item.output));
+    }
+}
+
+#[test]
+fn cross_validation_trains_a_network_per_fold() {
+    let mut dataset = Vec::new();
+    for k in 0..12 {
+        let x = k as f64 / 12.0;
+        dataset.push(DataSetItem {
+            input: vec![x, 1.0 - x],
+            output: vec![if x > 0.5 { 1.0 } else { 0.0 }],
+        });
+    }
+
+    let mut rng = SeededRng::from_seed(4);
+    let mut seed = 0;
+    let result = cross_validate(&dataset, 3, true, &mut rng, |train, validation| {
+        let mut network = create_network(ErrorFunctionKey::LogLoss);
+        network.seed(seed);
+        seed += 1;
+        network.add(ActivationFunctionKey::Sigmoid, 1);
+        network.initialize(2, None);
+
+        let mut optimizer = sgd_optimizer(Constant(2.0));
+        for i in 0..500 {
+            let data = network.compute_gradients(train);
+            optimizer.do_update(&data, &mut network, i);
+        }
+        evaluate(&network, validation)
+    });
+
+    assert_eq!(result.folds.len(), 3);
+    assert!(result.mean.accuracy > 0.9, "{:?}", result.mean);
+}
+
+#[test]
+fn built_network_with_dropout_and_l2_trains() {
+    let dataset = cluster_dataset();
+    let mut network = NetworkBuilder::new(2)
+        .loss(ErrorFunctionKey::CrossEntropy)
+        .seed(3)
+        .dense(16)
+        .activation(ActivationFunctionKey::TanH)
+        .dropout(0.25)
+        .regularizer(RegularizerKey::L2(1e-3))
+        .dense(3)
+        .activation(ActivationFunctionKey::Softmax)
+        .build()
+        .unwrap();
+
+    let mut optimizer = sgd_optimizer(Constant(0.3));
+    for i in 0..400 {
+        let data = network.compute_gradients(&dataset);
+        optimizer.do_update(&data, &mut network, i);
+    }
+
+    let result = evaluate(&network, &dataset);
+    assert_eq!(result.accuracy, 1.0);
+    assert!(result.loss < 0.3, "loss {}", result.loss);
+}
+
+/// 5x5 images holding a single horizontal or vertical bar.
+fn bars_dataset() -> Vec<DataSetItem> {
+    let mut dataset = Vec::new();
+    for position in 0..5 {
+        for vertical in [false, true] {
+            let input = (0..25)
+                .map(|i| {
+                    let (y, x) = (i / 5, i % 5);
+                    let on = if vertical {
+                        x == position
+                    } else {
+                        y == position
+                    };
+                    if on {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                })
+                .collect();
+            let output = if vertical {
+                vec![0.0, 1.0]
+            } else {
+                vec![1.0, 0.0]
+            };
+            dataset.push(DataSetItem { input, output });
+        }
+    }
+    dataset
+}
+
+#[test]
+fn convolutional_network_learns_bar_orientation() {
+    let dataset = bars_dataset();
+    let mut network = NetworkBuilder::image(1, 5, 5)
+        .loss(ErrorFunctionKey::CrossEntropy)
+        .seed(7)
+        .conv2d(4, 3)
+        .padding(1)
+        .activation(ActivationFunctionKey::TanH)
+        .conv2d(4, 3)
+        .stride(2)
+        .flatten()
+        .dense(2)
+        .activation(ActivationFunctionKey::Softmax)
+        .build()
+        .unwrap();
+
+    let mut optimizer = sgd_optimizer(Constant(0.1));
+    optimizer.momentum = 0.9;
+    let initial_loss = network.compute_gradients(&dataset).loss;
+    for i in 0..150 {
+        let data = network.compute_gradients(&dataset);
+        optimizer.do_update(&data, &mut network, i);
+    }
+
+    let result = evaluate(&network, &dataset);
+    assert_eq!(result.accuracy, 1.0);
+    assert!(result.loss < initial_loss / 10.0, "loss {}", result.loss);
+}
+
+#[test]
+fn pooled_network_learns_bar_orientation() {
+    let dataset = bars_dataset();
+    let mut network = NetworkBuilder::image(1, 5, 5)
+        .loss(ErrorFunctionKey::CrossEntropy)
+        .seed(11)
+        .conv2d(4, 3)
+        .padding(1)
+        .activation(ActivationFunctionKey::TanH)
+        .max_pool2d(2)
+        .stride(1)
+        .conv2d(4, 3)
+        .padding(1)
+        .global_avg_pool()
+        .dense(2)
+        .activation(ActivationFunctionKey::Softmax)
+        .build()
+        .unwrap();
+
+    let mut optimizer = sgd_optimizer(Constant(0.1));
+    optimizer.momentum = 0.9;
+    for i in 0..200 {
+        let data = network.compute_gradients(&dataset);
+        optimizer.do_update(&data, &mut network, i);
+    }
+
+    assert_eq!(evaluate(&network, &dataset).accuracy, 1.0);
+}